@@ -0,0 +1,59 @@
+use std::{path::PathBuf, process::Command};
+
+/// Same as the one in `src/catalog.rs`
+const CATALOG_URL: &str = "https://wiki.t2linux.org/tools/distro-metadata.json";
+const SNAPSHOT: &str = "assets/distro-metadata.json";
+/// Set to 1 to build with the current catalog, instead of the snapshot in `assets`
+const REFRESH_VAR: &str = "T2LINUX_REFRESH_CATALOG";
+const TRUSTED_KEYS: &str = "assets/trusted-keys.pub";
+
+/// Picks the catalog built into the installer, as `T2LINUX_BUILTIN_CATALOG`
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={TRUSTED_KEYS}");
+    let keys = std::fs::read_to_string(TRUSTED_KEYS).expect("Failed to read the trusted keys");
+    if keys
+        .lines()
+        .all(|line| line.is_empty() || line.starts_with('#'))
+    {
+        println!(
+            "cargo:warning=No catalog signing key is pinned in {TRUSTED_KEYS}, so no catalog \
+             can be verified"
+        );
+    }
+    println!("cargo:rerun-if-changed={SNAPSHOT}");
+    println!("cargo:rerun-if-env-changed={REFRESH_VAR}");
+    let catalog = if std::env::var(REFRESH_VAR).is_ok_and(|v| v == "1") {
+        let path = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("distro-metadata.json");
+        let status = Command::new("curl")
+            .args([
+                "--fail",
+                "--silent",
+                "--show-error",
+                "--location",
+                "--output",
+            ])
+            .arg(&path)
+            .arg(CATALOG_URL)
+            .status()
+            .expect("Failed to run curl");
+        assert!(status.success(), "Failed to download {CATALOG_URL}");
+        path
+    } else {
+        PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap()).join(SNAPSHOT)
+    };
+    let data = std::fs::read(&catalog).expect("Failed to read the distro catalog");
+    let parsed: serde_json::Value =
+        serde_json::from_slice(&data).expect("Failed to parse the distro catalog");
+    let distros = parsed["all"].as_array().map_or(0, Vec::len);
+    if distros == 0 {
+        println!(
+            "cargo:warning=The built in distro catalog is empty, build with {REFRESH_VAR}=1 or \
+             update {SNAPSHOT}"
+        );
+    }
+    println!(
+        "cargo:rustc-env=T2LINUX_BUILTIN_CATALOG={}",
+        catalog.display()
+    );
+}
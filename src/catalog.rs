@@ -0,0 +1,215 @@
+use crate::distro::Distro;
+use crate::fetch;
+use crate::signature::{self, Verified};
+use anyhow::{Context, Result, anyhow};
+use reqwest::{
+    StatusCode, Url,
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+const DEFAULT_URL: &str = "https://wiki.t2linux.org/tools/distro-metadata.json";
+/// Overrides where the catalog is loaded from, either a url or a local path
+pub const URL_VAR: &str = "T2LINUX_DISTRO_METADATA";
+/// Used when the catalog can't be fetched, and there is no cached copy.
+///
+/// This is `assets/distro-metadata.json`, or the current catalog when built with
+/// `T2LINUX_REFRESH_CATALOG=1`. It is part of the installer, so it is trusted without a
+/// signature.
+const BUILTIN: &[u8] = include_bytes!(env!("T2LINUX_BUILTIN_CATALOG"));
+
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    pub distros: Vec<Distro>,
+    pub source: CatalogSource,
+    /// The catalog had no signature, so its urls and checksums could not be checked
+    pub unsigned: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum CatalogSource {
+    Remote,
+    Local(PathBuf),
+    /// The last catalog that was fetched, as fetching it failed
+    Cache(Arc<anyhow::Error>),
+    /// The catalog included in the installer, as fetching it failed
+    BuiltIn(Arc<anyhow::Error>),
+}
+
+/// The last catalog that was fetched, kept to revalidate it and in case the next fetch fails
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedCatalog {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    data: String,
+    signature: Option<String>,
+}
+
+impl CachedCatalog {
+    /// The cache could have been changed since it was written, so it is checked again
+    fn parse(&self) -> Result<(Vec<Distro>, Verified)> {
+        parse_signed(self.data.as_bytes(), self.signature.as_deref())
+    }
+}
+
+enum Location {
+    Url(Url),
+    Path(PathBuf),
+}
+
+impl Catalog {
+    pub async fn load() -> Result<Catalog> {
+        match location()? {
+            Location::Path(path) => {
+                let data = tokio::fs::read(&path)
+                    .await
+                    .with_context(|| format!("Failed to read distro catalog {}", path.display()))?;
+                let signature = tokio::fs::read_to_string(signature_path(&path)).await.ok();
+                let (distros, verified) = parse_signed(&data, signature.as_deref())?;
+                Ok(Catalog {
+                    distros,
+                    source: CatalogSource::Local(path),
+                    unsigned: verified == Verified::Unsigned,
+                })
+            }
+            Location::Url(url) => {
+                let cached = read_cache(&url).await;
+                match fetch(&url, cached.as_ref()).await {
+                    Ok((distros, verified)) => Ok(Catalog {
+                        distros,
+                        source: CatalogSource::Remote,
+                        unsigned: verified == Verified::Unsigned,
+                    }),
+                    Err(e) => {
+                        let e =
+                            Arc::new(e.context(format!("Failed to fetch distro catalog {url}")));
+                        if let Some(cached) = cached
+                            && let Ok((distros, verified)) = cached.parse()
+                        {
+                            return Ok(Catalog {
+                                distros,
+                                source: CatalogSource::Cache(e),
+                                unsigned: verified == Verified::Unsigned,
+                            });
+                        }
+                        Ok(Catalog {
+                            distros: Distro::parse_all(BUILTIN)
+                                .context("Failed to parse built in distro catalog")?,
+                            source: CatalogSource::BuiltIn(e),
+                            unsigned: false,
+                        })
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn location() -> Result<Location> {
+    let Ok(location) = std::env::var(URL_VAR) else {
+        return Ok(Location::Url(Url::parse(DEFAULT_URL)?));
+    };
+    match Url::parse(&location) {
+        Ok(url) if url.scheme() == "file" => {
+            Ok(Location::Path(url.to_file_path().map_err(|_| {
+                anyhow!("Invalid distro catalog path: {location}")
+            })?))
+        }
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(Location::Url(url)),
+        _ => Ok(Location::Path(PathBuf::from(location))),
+    }
+}
+
+async fn fetch(url: &Url, cached: Option<&CachedCatalog>) -> Result<(Vec<Distro>, Verified)> {
+    // A stalled connection would otherwise keep the cache and built in catalog from being used
+    let client = fetch::client()?;
+    let mut request = client.get(url.clone());
+    if let Some(cached) = cached {
+        if let Some(etag) = &cached.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let response = request.send().await?;
+    if let Some(cached) = cached
+        && response.status() == StatusCode::NOT_MODIFIED
+    {
+        return cached.parse();
+    }
+    let response = response.error_for_status()?;
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned)
+    };
+    let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+    let data = response.text().await?;
+    let signature = fetch_signature(&client, url).await?;
+    let parsed = parse_signed(data.as_bytes(), signature.as_deref())?;
+    // Failing to cache the catalog only matters the next time it can't be fetched
+    let _ = write_cache(&CachedCatalog {
+        url: url.to_string(),
+        etag,
+        last_modified,
+        data,
+        signature,
+    })
+    .await;
+    Ok(parsed)
+}
+
+/// Fetches the detached minisign signature next to the catalog, if there is one
+async fn fetch_signature(client: &reqwest::Client, url: &Url) -> Result<Option<String>> {
+    let mut signature_url = url.clone();
+    signature_url.set_path(&format!("{}.minisig", url.path()));
+    let response = client.get(signature_url).send().await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(response.error_for_status()?.text().await?))
+}
+
+fn signature_path(path: &Path) -> PathBuf {
+    let mut path = OsString::from(path.as_os_str());
+    path.push(".minisig");
+    PathBuf::from(path)
+}
+
+/// Parses the catalog, once its signature is checked against the trusted keys
+fn parse_signed(data: &[u8], signature: Option<&str>) -> Result<(Vec<Distro>, Verified)> {
+    let verified = signature::verify("distro catalog", data, signature)?;
+    Ok((Distro::parse_all(data)?, verified))
+}
+
+fn cache_path() -> Option<PathBuf> {
+    Some(
+        dirs::cache_dir()?
+            .join("t2linux-installer")
+            .join("distro-metadata.json"),
+    )
+}
+
+async fn read_cache(url: &Url) -> Option<CachedCatalog> {
+    let data = tokio::fs::read(cache_path()?).await.ok()?;
+    let cached: CachedCatalog = serde_json::from_slice(&data).ok()?;
+    (cached.url == url.as_str()).then_some(cached)
+}
+
+async fn write_cache(cached: &CachedCatalog) -> Result<()> {
+    let path = cache_path().context("No cache directory")?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, serde_json::to_vec(cached)?).await?;
+    Ok(())
+}
@@ -0,0 +1,398 @@
+use crate::catalog::{Catalog, CatalogSource};
+use crate::disk::{self, BlockDevice};
+use crate::distro::Distro;
+use crate::event::Event;
+use crate::fetch::RETRIES;
+use crate::install::{DownloadTarget, InstallProgress, InstallSettings, IsoSource};
+use crate::local_iso::LocalIso;
+use crate::progress::ByteProgress;
+use crate::resume;
+use crate::ui::format;
+use crate::verify;
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{Parser, Subcommand};
+use futures::StreamExt;
+use humansize::{DECIMAL, format_size};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::fs::{File, OpenOptions};
+use tokio_util::sync::CancellationToken;
+
+/// How often progress is printed
+const REPORT_PERIOD: Duration = Duration::from_secs(1);
+
+/// Installation helper for linux on t2 macs, starts the installer window without a command
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// List the distros in the catalog
+    ListDistros,
+    /// List the disks that can be flashed
+    ListDisks,
+    /// Download the ISO of a distro into a file
+    Download {
+        /// Name of the distro, as listed by list-distros
+        distro: String,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Print progress to stdout as JSON lines, instead of text to stderr
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write the ISO of a distro, or an ISO file, to a disk
+    Flash {
+        /// Name of the distro, or path to an ISO or IMG file
+        source: String,
+        /// Disk as listed by list-disks, by its /dev path or its stable id
+        #[arg(short, long)]
+        device: String,
+        /// Erase the disk without asking, unmounting and locking what is in use on it
+        #[arg(short, long)]
+        yes: bool,
+        /// Print progress to stdout as JSON lines, instead of text to stderr
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check that a file or disk holds the ISO of a distro
+    Verify {
+        /// Downloaded ISO file, or disk it was written to
+        target: PathBuf,
+        /// Distro, or ISO file, to check against, instead of every distro in the catalog
+        #[arg(short, long)]
+        source: Option<String>,
+    },
+}
+
+/// Runs `command` without the window, returning the exit code
+pub fn run(command: Command) -> i32 {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return 1;
+        }
+    };
+    let json = matches!(
+        command,
+        Command::Download { json: true, .. } | Command::Flash { json: true, .. }
+    );
+    match runtime.block_on(run_command(command)) {
+        Ok(()) => 0,
+        Err(e) => {
+            if json {
+                Event::failed(&e).print();
+            }
+            eprintln!("Error: {e:#}");
+            1
+        }
+    }
+}
+
+async fn run_command(command: Command) -> Result<()> {
+    match command {
+        Command::ListDistros => {
+            for distro in load_catalog().await? {
+                println!(
+                    "{}\t{}\t{}",
+                    distro.name,
+                    distro.version.as_deref().unwrap_or("-"),
+                    distro
+                        .download_size
+                        .map(|size| format_size(size, DECIMAL))
+                        .unwrap_or("-".to_owned())
+                );
+            }
+        }
+        Command::ListDisks => {
+            for disk in disk::get_external_disks().await? {
+                // The id is what `flash --device` takes
+                println!("{}\t{}", format::disk_summary(&disk), disk.id);
+            }
+        }
+        Command::Download {
+            distro,
+            output,
+            json,
+        } => {
+            let distro = find_distro(&load_catalog().await?, &distro)?;
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                // Left to the installer, which can resume into an existing file
+                .truncate(false)
+                .open(&output)
+                .await
+                .with_context(|| format!("Failed to open {}", output.display()))?;
+            install(
+                InstallSettings::new(IsoSource::Distro(distro), DownloadTarget::File(output)),
+                file,
+                json,
+            )
+            .await?;
+        }
+        Command::Flash {
+            source,
+            device,
+            yes,
+            json,
+        } => {
+            let distros = load_catalog().await?;
+            let mut source = find_source(&distros, &source).await?;
+            if let IsoSource::Distro(distro) = &mut source {
+                distro.probe_size().await;
+            }
+            let device = find_disk(&device).await?;
+            let settings = InstallSettings::new(source, DownloadTarget::BlockDev(device.clone()));
+            settings.check_target()?;
+            confirm_erase(&settings, &device, yes)?;
+            if device.partitions_in_use().next().is_some() {
+                disk::unmount_disk(&device).await?;
+            }
+            let file = disk::get_fd_for_disk(device.clone())
+                .await
+                .with_context(|| format!("Failed to open /dev/{}", device.os_identifier))?;
+            install(settings, file, json).await?;
+        }
+        Command::Verify { target, source } => verify_target(&target, source.as_deref()).await?,
+    }
+    Ok(())
+}
+
+async fn load_catalog() -> Result<Vec<Distro>> {
+    let catalog = Catalog::load().await?;
+    if let CatalogSource::Cache(e) | CatalogSource::BuiltIn(e) = &catalog.source {
+        eprintln!("Warning: {e:#}");
+    }
+    if catalog.unsigned {
+        eprintln!("Warning: the distro catalog is not signed, its download urls are not checked");
+    }
+    Ok(catalog.distros)
+}
+
+fn find_distro(distros: &[Distro], name: &str) -> Result<Distro> {
+    distros
+        .iter()
+        .find(|d| d.name.eq_ignore_ascii_case(name))
+        .cloned()
+        .with_context(|| format!("There is no distro called {name}, see list-distros"))
+}
+
+/// A path to an ISO file, or else the name of a distro
+async fn find_source(distros: &[Distro], source: &str) -> Result<IsoSource> {
+    let path = Path::new(source);
+    if !path.exists() {
+        return Ok(IsoSource::Distro(find_distro(distros, source)?));
+    }
+    eprintln!("Looking for {source} in the catalog");
+    let iso = LocalIso::identify(path.to_owned(), distros).await?;
+    match &iso.distro {
+        Some(distro) => eprintln!("{source} is a download of {}", distro.name),
+        None => eprintln!("{source} is not in the catalog, it is written as is"),
+    }
+    Ok(IsoSource::Local(iso))
+}
+
+async fn find_disk(device: &str) -> Result<BlockDevice> {
+    let id = device.strip_prefix("/dev/").unwrap_or(device);
+    disk::get_external_disks()
+        .await?
+        .into_iter()
+        .find(|d| d.os_identifier == id || d.id == device)
+        .with_context(|| format!("There is no disk called {device}, see list-disks"))
+}
+
+/// Shows what is on `device` and what will be written to it, and asks before it is erased
+/// unless `yes` is set
+fn confirm_erase(settings: &InstallSettings, device: &BlockDevice, yes: bool) -> Result<()> {
+    eprintln!("{}", format::disk_summary(device));
+    for partition in &device.partitions {
+        eprintln!("  {}", format::partition_summary(partition));
+    }
+    eprintln!("{}", format::image_size_summary(settings.source(), device));
+    if yes {
+        return Ok(());
+    }
+    eprint!(
+        "Everything on /dev/{} will be erased, continue? [y/N] ",
+        device.os_identifier
+    );
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        bail!(
+            "/dev/{} was left as it is, --yes erases it without asking",
+            device.os_identifier
+        );
+    }
+    Ok(())
+}
+
+/// Runs the installer, printing its progress as text, or as JSON lines when `json` is set
+async fn install(settings: InstallSettings, file: File, json: bool) -> Result<()> {
+    let ct = CancellationToken::new();
+    let cancel = ct.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            cancel.cancel();
+        }
+    });
+    if json {
+        Event::Start {
+            source: settings.source().to_string(),
+            target: settings.download_target().to_string(),
+        }
+        .print();
+    }
+    let mut reporter = Reporter::default();
+    let mut progress = settings.install(Arc::new(file), ct).boxed();
+    while let Some(event) = progress.next().await {
+        match event {
+            // Failures are printed with the ones from outside the installer
+            InstallProgress::Failed(e) => return Err(e.into()),
+            InstallProgress::Finished if json => {
+                Event::Finished.print();
+                return Ok(());
+            }
+            InstallProgress::Finished => {
+                eprintln!("Done");
+                return Ok(());
+            }
+            event if json => Event::from(&event).print(),
+            event => reporter.report(event),
+        }
+    }
+    Err(anyhow!("Installer stopped without finishing"))
+}
+
+/// Prints progress to stderr, at most every `REPORT_PERIOD` for progress bars
+#[derive(Debug, Default)]
+struct Reporter {
+    reported_at: Option<Instant>,
+    write_rate: Option<f64>,
+}
+
+impl Reporter {
+    fn report(&mut self, event: InstallProgress) {
+        match event {
+            InstallProgress::IsoCached => eprintln!("Copying the cached ISO"),
+            InstallProgress::IsoDownloadStart(parts) => {
+                self.write_rate = None;
+                eprintln!("Downloading the ISO in {parts} part(s)");
+            }
+            InstallProgress::IsoDownloadProgress(part, progress) => {
+                let write_rate = self
+                    .write_rate
+                    .map(|rate| format!(" · writing at {}/s", format_size(rate as u64, DECIMAL)))
+                    .unwrap_or_default();
+                self.progress(format!("Part {part}"), progress, &write_rate);
+            }
+            InstallProgress::IsoPartChecksumFailed(part) => {
+                eprintln!("Part {part} was corrupted, downloading it again")
+            }
+            InstallProgress::IsoMirror(url) => eprintln!("Downloading from {url}"),
+            InstallProgress::IsoRetry(attempt, delay) => eprintln!(
+                "Connection lost, retrying in {}s (attempt {attempt} of {RETRIES})",
+                delay.as_secs()
+            ),
+            InstallProgress::IsoWriteProgress(progress) => {
+                self.write_rate = Some(progress.rate).filter(|rate| *rate > 0.0)
+            }
+            InstallProgress::VerifyStart => {
+                self.reported_at = None;
+                eprintln!("Verifying the disk");
+            }
+            InstallProgress::VerifyProgress(progress) => {
+                self.progress("Verified".to_owned(), progress, "")
+            }
+            InstallProgress::Finished | InstallProgress::Failed(_) => {}
+        }
+    }
+
+    fn progress(&mut self, label: String, progress: ByteProgress, extra: &str) {
+        let finished = progress.total.is_some_and(|total| progress.done >= total);
+        if finished
+            || self
+                .reported_at
+                .is_none_or(|at| at.elapsed() >= REPORT_PERIOD)
+        {
+            self.reported_at = Some(Instant::now());
+            eprintln!("{label}: {progress}{extra}");
+        }
+    }
+}
+
+/// Checks `target` against `source`, or against every distro in the catalog
+async fn verify_target(target: &Path, source: Option<&str>) -> Result<()> {
+    let mut candidates = vec![];
+    match source {
+        Some(source) if Path::new(source).exists() => {
+            let file =
+                std::fs::File::open(source).with_context(|| format!("Failed to open {source}"))?;
+            let len = file.metadata()?.len();
+            let hasher = resume::hash_range(Arc::new(file), 0, len, Sha256::new()).await?;
+            candidates.push((source.to_owned(), len, hasher.finalize().to_vec()));
+        }
+        Some(source) => {
+            let distro = find_distro(&load_catalog().await?, source)?;
+            candidates.push(
+                distro_checksum(&distro)?
+                    .with_context(|| format!("{source} can only be checked against its ISO"))?,
+            );
+        }
+        None => {
+            for distro in load_catalog().await? {
+                candidates.extend(distro_checksum(&distro)?);
+            }
+        }
+    }
+    // Only the start of a disk holds the ISO, so each length is hashed on the way
+    candidates.sort_by_key(|(_, len, _)| *len);
+    let file = std::fs::File::open(target)
+        .with_context(|| format!("Failed to open {}", target.display()))?;
+    verify::drop_caches(&file)?;
+    // The metadata of a disk has no length
+    let size = (&file).seek(SeekFrom::End(0))?;
+    let file = Arc::new(file);
+    let (mut hasher, mut hashed) = (Sha256::new(), 0);
+    for (name, len, sha256) in candidates {
+        if len > size {
+            break;
+        }
+        hasher = resume::hash_range(file.clone(), hashed, len - hashed, hasher).await?;
+        hashed = len;
+        if hasher.clone().finalize().as_slice() == sha256 {
+            println!("{} holds {name}", target.display());
+            return Ok(());
+        }
+    }
+    Err(anyhow!(
+        "{} does not match {}",
+        target.display(),
+        source.unwrap_or("any distro in the catalog")
+    ))
+}
+
+/// Length and checksum of the ISO of `distro`, which the catalog only has when it is not
+/// compressed
+fn distro_checksum(distro: &Distro) -> Result<Option<(String, u64, Vec<u8>)>> {
+    let (None, Some(sum), Some(len)) =
+        (distro.compression(), distro.sha256(), distro.download_size)
+    else {
+        return Ok(None);
+    };
+    distro.check_signature()?;
+    let sum = hex::decode(sum).context("Could not decode checksum")?;
+    Ok(Some((distro.name.clone(), len, sum)))
+}
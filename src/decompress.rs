@@ -0,0 +1,231 @@
+use crate::distro::CompressionAlgorithim;
+use flate2::read::MultiGzDecoder;
+use std::io::{self, ErrorKind, Read, Write};
+use xz2::read::XzDecoder;
+
+/// Reader that also writes everything it reads into `copy`, when set.
+pub struct TeeReader<R, W: Write> {
+    reader: R,
+    copy: Option<W>,
+}
+
+impl<R, W: Write> TeeReader<R, W> {
+    pub fn new(reader: R, copy: Option<W>) -> Self {
+        Self { reader, copy }
+    }
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.reader.read(buf)?;
+        if let Some(copy) = &mut self.copy {
+            copy.write_all(&buf[..len])?;
+            if len == 0 {
+                copy.flush()?;
+            }
+        }
+        Ok(len)
+    }
+}
+
+/// Decompresses `reader` into `writer`, returning the number of bytes written.
+///
+/// Anything left in `reader` afterwards is read and discarded, so that the sender
+/// always gets to push the whole download through.
+pub fn copy(
+    compression: Option<&CompressionAlgorithim>,
+    mut reader: impl Read,
+    mut writer: impl Write,
+) -> io::Result<u64> {
+    let written = match compression {
+        None => io::copy(&mut reader, &mut writer)?,
+        Some(CompressionAlgorithim::Zip) => copy_zip(&mut reader, &mut writer)?,
+        Some(CompressionAlgorithim::Xz) => {
+            io::copy(&mut XzDecoder::new_multi_decoder(&mut reader), &mut writer)?
+        }
+        Some(CompressionAlgorithim::Zstd) => {
+            io::copy(&mut zstd::Decoder::new(&mut reader)?, &mut writer)?
+        }
+        Some(CompressionAlgorithim::Gzip) => {
+            io::copy(&mut MultiGzDecoder::new(&mut reader), &mut writer)?
+        }
+    };
+    writer.flush()?;
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(written)
+}
+
+/// Bytes of a zip archive [`check_zip`] needs, up to the flags of the first local header
+pub const ZIP_HEADER_LEN: usize = 8;
+const ZIP_LOCAL_HEADER: &[u8] = b"PK\x03\x04";
+/// Flags of entries that can't be extracted from a stream
+const ZIP_ENCRYPTED: u16 = 1;
+const ZIP_DATA_DESCRIPTOR: u16 = 1 << 3;
+
+/// Checks that the first entry of a zip archive can be extracted while it is streamed, from
+/// the first [`ZIP_HEADER_LEN`] bytes of the archive.
+///
+/// An entry written with a data descriptor only has its length after its data, which a
+/// stream can't be cut at.
+pub fn check_zip(header: &[u8]) -> io::Result<()> {
+    let Some(flags) = header
+        .get(..ZIP_HEADER_LEN)
+        .filter(|header| header.starts_with(ZIP_LOCAL_HEADER))
+        .map(|header| u16::from_le_bytes([header[6], header[7]]))
+    else {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Zip archive does not start with a file",
+        ));
+    };
+    if flags & ZIP_ENCRYPTED != 0 {
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "Zip archive is encrypted",
+        ));
+    }
+    if flags & ZIP_DATA_DESCRIPTOR != 0 {
+        return Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "Zip archive stores the length of the image after it, so it can't be extracted while it is downloaded",
+        ));
+    }
+    Ok(())
+}
+
+/// Extracts the first file in a zip archive, ignoring directory entries.
+fn copy_zip(reader: &mut impl Read, writer: &mut impl Write) -> io::Result<u64> {
+    loop {
+        let Some(mut entry) = zip::read::read_zipfile_from_stream(reader)? else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Zip archive does not contain any files",
+            ));
+        };
+        if entry.is_dir() {
+            continue;
+        }
+        return io::copy(&mut entry, writer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    const IMAGE: &[u8] = b"not really an iso, but it is the first file";
+
+    /// Archive holding a directory, then the image
+    fn zip() -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        writer
+            .add_directory("boot", SimpleFileOptions::default())
+            .unwrap();
+        writer
+            .start_file("image.iso", SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(IMAGE).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn zip_with_lengths_up_front() {
+        let archive = zip();
+        check_zip(&archive[..ZIP_HEADER_LEN]).unwrap();
+        let mut image = vec![];
+        let written = copy(Some(&CompressionAlgorithim::Zip), &archive[..], &mut image).unwrap();
+        assert_eq!((written, &image[..]), (IMAGE.len() as u64, IMAGE));
+    }
+
+    #[test]
+    fn zip_with_data_descriptor_is_refused() {
+        let mut archive = zip();
+        archive[6] |= ZIP_DATA_DESCRIPTOR as u8;
+        let e = check_zip(&archive[..ZIP_HEADER_LEN]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Unsupported);
+        // Which is what extracting it would only find out once the disk was erased
+        let e = copy(Some(&CompressionAlgorithim::Zip), &archive[..], io::sink()).unwrap_err();
+        assert!(e.to_string().contains("local header"), "{e}");
+    }
+
+    /// Decompresses `data`, checking everything after the stream was read too
+    fn round_trip(compression: CompressionAlgorithim, data: &[u8]) -> Vec<u8> {
+        let mut reader = Cursor::new(data);
+        let mut image = vec![];
+        let written = copy(Some(&compression), &mut reader, &mut image).unwrap();
+        assert_eq!(written, image.len() as u64);
+        assert_eq!(reader.position(), data.len() as u64);
+        image
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn xz(data: &[u8]) -> Vec<u8> {
+        let mut encoder = xz2::write::XzEncoder::new(vec![], 1);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn uncompressed() {
+        let mut image = vec![];
+        assert_eq!(copy(None, IMAGE, &mut image).unwrap(), IMAGE.len() as u64);
+        assert_eq!(image, IMAGE);
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        assert_eq!(round_trip(CompressionAlgorithim::Gzip, &gzip(IMAGE)), IMAGE);
+        // Like `gzip -c a b`, which `zcat` reads as one file
+        let streams = [gzip(b"first, "), gzip(b"second")].concat();
+        assert_eq!(
+            round_trip(CompressionAlgorithim::Gzip, &streams),
+            b"first, second"
+        );
+    }
+
+    #[test]
+    fn xz_round_trip() {
+        assert_eq!(round_trip(CompressionAlgorithim::Xz, &xz(IMAGE)), IMAGE);
+        let streams = [xz(b"first, "), xz(b"second")].concat();
+        assert_eq!(
+            round_trip(CompressionAlgorithim::Xz, &streams),
+            b"first, second"
+        );
+    }
+
+    #[test]
+    fn zstd_round_trip() {
+        let compressed = zstd::encode_all(IMAGE, 1).unwrap();
+        assert_eq!(round_trip(CompressionAlgorithim::Zstd, &compressed), IMAGE);
+    }
+
+    #[test]
+    fn corrupt_data_fails() {
+        let mut compressed = xz(IMAGE);
+        let middle = compressed.len() / 2;
+        compressed[middle] ^= 0xff;
+        let result = copy(
+            Some(&CompressionAlgorithim::Xz),
+            &compressed[..],
+            io::sink(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn not_a_zip() {
+        for header in [&b"PK\x03"[..], b"\x7fELF\x02\x01\x01\x00"] {
+            assert_eq!(
+                check_zip(header).unwrap_err().kind(),
+                ErrorKind::InvalidData
+            );
+        }
+    }
+}
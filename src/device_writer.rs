@@ -0,0 +1,326 @@
+use std::{
+    fs::File,
+    io::{self, Seek, Write},
+    os::unix::fs::FileExt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::JoinHandle,
+};
+
+/// Size of each write handed to the disk
+const BUFFER_LEN: usize = 4 << 20;
+/// Alignment `O_DIRECT` needs for buffers, offsets and lengths, enough for 4K sector disks
+const ALIGN: usize = 4096;
+/// Set to 1 to write with `O_DIRECT`, bypassing the page cache
+pub const DIRECT_IO_VAR: &str = "T2LINUX_DIRECT_IO";
+/// Number of writes the disk is given at once
+pub const QUEUE_DEPTH_VAR: &str = "T2LINUX_QUEUE_DEPTH";
+const DEFAULT_QUEUE_DEPTH: usize = 4;
+/// `_IO(0x12, 97)`, which libc does not have
+#[cfg(target_os = "linux")]
+const BLKFLSBUF: libc::Ioctl = 0x1261;
+
+#[derive(Debug, Clone, Copy)]
+pub struct WriterOptions {
+    pub direct: bool,
+    pub queue_depth: usize,
+}
+
+impl WriterOptions {
+    pub fn from_env() -> Self {
+        Self {
+            direct: std::env::var(DIRECT_IO_VAR).is_ok_and(|v| v == "1"),
+            queue_depth: std::env::var(QUEUE_DEPTH_VAR)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|depth| *depth > 0)
+                .unwrap_or(DEFAULT_QUEUE_DEPTH),
+        }
+    }
+}
+
+/// Buffer that starts on an `ALIGN` boundary
+struct AlignedBuf {
+    data: Vec<u8>,
+    start: usize,
+    len: usize,
+}
+
+impl AlignedBuf {
+    fn new() -> Self {
+        let data = vec![0; BUFFER_LEN + ALIGN];
+        // The allocation does not move with the Vec, so this stays aligned
+        let start = data.as_ptr().align_offset(ALIGN);
+        Self {
+            data,
+            start,
+            len: 0,
+        }
+    }
+
+    fn filled(&self) -> &[u8] {
+        &self.data[self.start..self.start + self.len]
+    }
+
+    fn spare(&mut self) -> &mut [u8] {
+        &mut self.data[self.start + self.len..self.start + BUFFER_LEN]
+    }
+}
+
+/// Writes to a disk in large aligned buffers, with several writes in flight at once.
+///
+/// A regular file gets one write at a time, so it never has a hole before its end.
+///
+/// Flushing waits for every write, and for the disk itself.
+pub struct DeviceWriter {
+    file: Arc<File>,
+    direct: bool,
+    queue_depth: usize,
+    /// Where `buf` goes
+    offset: u64,
+    buf: AlignedBuf,
+    spare: Vec<AlignedBuf>,
+    allocated: usize,
+    /// Buffers handed to the workers, and not returned yet
+    in_flight: usize,
+    jobs: Option<Sender<(AlignedBuf, u64)>>,
+    done: Receiver<AlignedBuf>,
+    workers: Vec<JoinHandle<()>>,
+    failed: Arc<Mutex<Option<io::Error>>>,
+    written: Arc<AtomicU64>,
+}
+
+impl DeviceWriter {
+    /// Writes into `file`, from its current position
+    pub fn new(mut file: File, options: WriterOptions) -> io::Result<Self> {
+        let offset = file.stream_position()?;
+        // A file is resumed from its length, which only holds when writes land in order
+        let queue_depth = match file.metadata()?.is_file() {
+            true => 1,
+            false => options.queue_depth,
+        };
+        // Not every file system supports it, those just go through the page cache
+        let direct = options.direct
+            && offset.is_multiple_of(ALIGN as u64)
+            && set_direct(&file, true).is_ok();
+        let file = Arc::new(file);
+        let (jobs, job_rx) = mpsc::channel::<(AlignedBuf, u64)>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let (done_tx, done) = mpsc::channel();
+        let failed = Arc::new(Mutex::new(None));
+        let written = Arc::new(AtomicU64::new(0));
+        let workers = (0..queue_depth)
+            .map(|_| {
+                let (file, job_rx, done_tx) = (file.clone(), job_rx.clone(), done_tx.clone());
+                let (failed, written) = (failed.clone(), written.clone());
+                std::thread::spawn(move || {
+                    loop {
+                        let job = job_rx.lock().unwrap().recv();
+                        let Ok((mut buf, offset)) = job else {
+                            break;
+                        };
+                        // Once a write failed, the rest is only handed back
+                        if failed.lock().unwrap().is_none() {
+                            match file.write_all_at(buf.filled(), offset) {
+                                Ok(()) => {
+                                    written.fetch_add(buf.len as u64, Ordering::Relaxed);
+                                }
+                                Err(e) => *failed.lock().unwrap() = Some(e),
+                            }
+                        }
+                        buf.len = 0;
+                        if done_tx.send(buf).is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+        Ok(Self {
+            file,
+            direct,
+            queue_depth,
+            offset,
+            buf: AlignedBuf::new(),
+            spare: vec![],
+            allocated: 1,
+            in_flight: 0,
+            jobs: Some(jobs),
+            done,
+            workers,
+            failed,
+            written,
+        })
+    }
+
+    pub fn meter(&self) -> WrittenMeter {
+        WrittenMeter {
+            written: self.written.clone(),
+            reported: 0,
+        }
+    }
+
+    /// Fails once any write failed, and keeps failing, so nothing is written after it
+    fn check(&self) -> io::Result<()> {
+        match &*self.failed.lock().unwrap() {
+            Some(e) => Err(io::Error::new(e.kind(), e.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn next_buffer(&mut self) -> io::Result<AlignedBuf> {
+        if let Some(buf) = self.spare.pop() {
+            return Ok(buf);
+        }
+        if self.allocated <= self.queue_depth {
+            self.allocated += 1;
+            return Ok(AlignedBuf::new());
+        }
+        self.wait_one()
+    }
+
+    fn wait_one(&mut self) -> io::Result<AlignedBuf> {
+        let buf = self.done.recv().map_err(|_| stopped())?;
+        self.in_flight -= 1;
+        Ok(buf)
+    }
+
+    fn wait_idle(&mut self) -> io::Result<()> {
+        while self.in_flight > 0 {
+            let buf = self.wait_one()?;
+            self.spare.push(buf);
+        }
+        self.check()
+    }
+
+    fn submit(&mut self) -> io::Result<()> {
+        self.check()?;
+        let next = self.next_buffer()?;
+        let buf = std::mem::replace(&mut self.buf, next);
+        let offset = self.offset;
+        if self.direct && !(buf.len % ALIGN == 0 && offset.is_multiple_of(ALIGN as u64)) {
+            // Only the end of the data is not aligned
+            self.wait_idle()?;
+            set_direct(&self.file, false)?;
+            self.direct = false;
+        }
+        self.offset += buf.len as u64;
+        self.jobs
+            .as_ref()
+            .ok_or_else(stopped)?
+            .send((buf, offset))
+            .map_err(|_| stopped())?;
+        self.in_flight += 1;
+        Ok(())
+    }
+}
+
+impl Write for DeviceWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let spare = self.buf.spare();
+        let len = spare.len().min(buf.len());
+        spare[..len].copy_from_slice(&buf[..len]);
+        self.buf.len += len;
+        if self.buf.len == BUFFER_LEN {
+            self.submit()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.len > 0 {
+            self.submit()?;
+        }
+        self.wait_idle()?;
+        if self.direct {
+            // Whatever reads the file next might not use aligned buffers
+            set_direct(&self.file, false)?;
+            self.direct = false;
+        }
+        sync_device(&self.file)
+    }
+}
+
+impl Drop for DeviceWriter {
+    fn drop(&mut self) {
+        if self.buf.len > 0 && self.check().is_ok() {
+            let _ = self.submit();
+        }
+        let _ = self.wait_idle();
+        if self.direct {
+            let _ = set_direct(&self.file, false);
+        }
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Number of bytes of a [`DeviceWriter`] that reached the disk, apart from the download rate
+#[derive(Debug)]
+pub struct WrittenMeter {
+    written: Arc<AtomicU64>,
+    reported: u64,
+}
+
+impl WrittenMeter {
+    /// Bytes written so far, if that changed since the last call
+    pub fn poll(&mut self) -> Option<u64> {
+        let written = self.written.load(Ordering::Relaxed);
+        (written != self.reported).then(|| {
+            self.reported = written;
+            written
+        })
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::other("Disk writer stopped")
+}
+
+/// Waits until everything written to `file` is on the disk, and empties the kernel's
+/// buffers for it.
+pub fn sync_device(file: &File) -> io::Result<()> {
+    file.sync_all()?;
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::{fd::AsRawFd, unix::fs::FileTypeExt};
+        // Needs CAP_SYS_ADMIN, so it is only a best effort
+        if file.metadata()?.file_type().is_block_device() {
+            unsafe { libc::ioctl(file.as_raw_fd(), BLKFLSBUF) };
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_direct(file: &File, direct: bool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let flags = if direct {
+        flags | libc::O_DIRECT
+    } else {
+        flags & !libc::O_DIRECT
+    };
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn set_direct(file: &File, direct: bool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, direct as libc::c_int) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -1,9 +1,12 @@
-use anyhow::Result;
-use humansize::{DECIMAL, format_size};
+use anyhow::{Context, Result};
+use futures::{Stream, stream};
 use serde::Deserialize;
+use std::{path::PathBuf, process::Stdio};
+use tokio::io::{AsyncBufReadExt, BufReader};
 use uuid::Uuid;
 
-use super::BlockDevice;
+use super::{BlockDevice, Transport};
+use crate::error::Error;
 
 #[allow(dead_code)]
 #[derive(Deserialize, Debug)]
@@ -38,6 +41,21 @@ struct Partition {
     volume_UUID: Option<Uuid>,
 }
 
+/// The part of `diskutil info` for disks and partitions that is used
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase", default)]
+struct DiskInfo {
+    bus_protocol: Option<String>,
+    media_name: Option<String>,
+    removable: bool,
+    removable_media: bool,
+    writable_media: Option<bool>,
+    filesystem_type: Option<String>,
+    mount_point: Option<String>,
+    volume_name: Option<String>,
+    parent_whole_disk: Option<String>,
+}
+
 #[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "PascalCase")]
@@ -56,20 +74,129 @@ fn diskutil_cmd(args: Vec<&str>) -> Result<Vec<u8>> {
     Ok(cmd.stdout)
 }
 
+fn disk_info(identifier: &str) -> Result<DiskInfo> {
+    let diskutil_output = diskutil_cmd(vec!["info", "-plist", identifier])?;
+    Ok(plist::from_bytes(diskutil_output.as_ref())?)
+}
+
 pub fn get_external_disks() -> Result<Vec<BlockDevice>> {
     let diskutil_output = diskutil_cmd(vec!["list", "-plist", "external", "physical"])?;
     let all_disks: DiskList = plist::from_bytes(diskutil_output.as_ref()).unwrap();
+    // The system can be booted from an external disk
+    let system = disk_info("/")?.parent_whole_disk;
     let mut disks: Vec<BlockDevice> = vec![];
     for disk in all_disks.all_disks_and_partitions {
+        let info = disk_info(&disk.device_identifier)?;
+        let mut partitions = vec![];
+        for partition in disk.partitions {
+            // Missing details are not worth hiding the disk for
+            let part_info = disk_info(&partition.device_identifier).unwrap_or_default();
+            partitions.push(super::Partition {
+                os_identifier: partition.device_identifier,
+                size: partition.size,
+                label: part_info.volume_name.or(partition.volume_name),
+                filesystem: part_info.filesystem_type,
+                mountpoints: part_info
+                    .mount_point
+                    .filter(|mount_point| !mount_point.is_empty())
+                    .map(PathBuf::from)
+                    .into_iter()
+                    .collect(),
+                holders: vec![],
+            });
+        }
         disks.push(BlockDevice {
-            os_identifier: disk.device_identifier.to_string(),
-            name: disk.device_identifier,
-            size: format_size(disk.size, DECIMAL),
+            // diskutil has no serial number, and the disk UUID comes from the partition table,
+            // which every stick flashed with the same image shares. Identifiers are only
+            // handed out again once a disk is gone, so with the name and size this at least
+            // catches a different kind of disk taking its place
+            id: format!(
+                "{}:{}:{}",
+                disk.device_identifier,
+                info.media_name.as_deref().unwrap_or_default(),
+                disk.size
+            ),
+            os_identifier: disk.device_identifier,
+            size: disk.size,
+            // diskutil has no separate vendor, the media name usually starts with it
+            vendor: None,
+            model: info.media_name,
+            serial: None,
+            transport: transport(info.bus_protocol.as_deref()),
+            removable: info.removable || info.removable_media,
+            read_only: info.writable_media == Some(false),
+            system: system.as_ref() == Some(&disk.device_identifier),
+            partitions,
         });
     }
     Ok(disks)
 }
 
+/// Unmounts every volume of the disk `identifier`
+pub fn unmount_disk(identifier: &str) -> Result<()> {
+    let output = std::process::Command::new("diskutil")
+        .args(["unmountDisk", identifier])
+        .output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let message = String::from_utf8_lossy(&output.stderr);
+    // Like "Unmount of disk4 failed: at least one volume could not be unmounted",
+    // followed by "Unmount was dissented by PID 312 (/usr/bin/vim)"
+    let users: Vec<String> = message
+        .lines()
+        .filter_map(|line| line.split_once("dissented by PID ").map(|(_, user)| user))
+        .map(|user| user.trim().to_owned())
+        .collect();
+    if !users.is_empty() {
+        return Err(Error::MountBusy(PathBuf::from(format!("/dev/{identifier}")), users).into());
+    }
+    anyhow::bail!("Failed to unmount {identifier}: {}", message.trim())
+}
+
+/// The external disk with the device number `rdev`, as it is now
+pub fn disk_of_rdev(rdev: u64) -> Result<Option<BlockDevice>> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(get_external_disks()?.into_iter().find(|disk| {
+        std::fs::metadata(format!("/dev/{}", disk.os_identifier))
+            .is_ok_and(|metadata| metadata.rdev() == rdev)
+    }))
+}
+
+/// Fires each time a disk or partition appears or disappears, as `diskutil activity` prints
+/// them
+pub fn changes() -> Result<impl Stream<Item = ()>> {
+    let mut child = tokio::process::Command::new("diskutil")
+        .arg("activity")
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdout = child.stdout.take().context("diskutil has no output")?;
+    let lines = BufReader::new(stdout).lines();
+    Ok(stream::unfold(
+        (child, lines),
+        |(child, mut lines)| async move {
+            loop {
+                let line = lines.next_line().await.ok()??;
+                if line.starts_with("***DiskAppeared") || line.starts_with("***DiskDisappeared") {
+                    return Some(((), (child, lines)));
+                }
+            }
+        },
+    ))
+}
+
+fn transport(bus_protocol: Option<&str>) -> Transport {
+    match bus_protocol {
+        Some("USB") => Transport::Usb,
+        Some("Secure Digital") => Transport::Mmc,
+        Some("Thunderbolt") => Transport::Thunderbolt,
+        Some("PCI-Express" | "PCI") => Transport::Nvme,
+        Some("SATA" | "ATA") => Transport::Ata,
+        _ => Transport::Unknown,
+    }
+}
+
 pub fn get_resize_limits(disk: &str) -> Result<(u64, u64)> {
     let diskutil_output = diskutil_cmd(vec!["apfs", "resizeContainer", disk, "limits", "-plist"])?;
     let limits: ApfsResizeLimits = plist::from_bytes(diskutil_output.as_ref()).unwrap();
@@ -0,0 +1,139 @@
+use crate::disk::UDISKS_BLOCK_DEVICES;
+use anyhow::Result;
+use futures::{Stream, StreamExt, stream};
+use udisks2::zbus::{self, fdo::ObjectManagerProxy};
+
+/// Fires each time udisks2 on `connection` adds or removes a disk or partition
+pub async fn changes(connection: &zbus::Connection) -> Result<impl Stream<Item = ()> + use<>> {
+    let manager = ObjectManagerProxy::builder(connection)
+        .destination("org.freedesktop.UDisks2")?
+        .path("/org/freedesktop/UDisks2")?
+        .build()
+        .await?;
+    let added = manager.receive_interfaces_added().await?.map(|signal| {
+        signal
+            .args()
+            .is_ok_and(|args| args.object_path.starts_with(UDISKS_BLOCK_DEVICES))
+    });
+    let removed = manager.receive_interfaces_removed().await?.map(|signal| {
+        signal
+            .args()
+            .is_ok_and(|args| args.object_path.starts_with(UDISKS_BLOCK_DEVICES))
+    });
+    Ok(stream::select(added, removed)
+        .filter(|block_device| std::future::ready(*block_device))
+        .map(|_| ()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::HashMap,
+        io::{self, BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        time::Duration,
+    };
+    use udisks2::zbus::{
+        connection::Builder,
+        zvariant::{ObjectPath, Value},
+    };
+
+    /// A session bus of its own, gone once this is dropped
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        /// Starts `dbus-daemon`, or skips the test when it is not installed
+        fn start() -> Option<Self> {
+            let spawned = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn();
+            let mut daemon = match spawned {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    eprintln!("Skipped, testing hotplug needs dbus-daemon");
+                    return None;
+                }
+                spawned => spawned.unwrap(),
+            };
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.as_mut().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Some(Self {
+                daemon,
+                address: address.trim().to_owned(),
+            })
+        }
+
+        async fn connect(&self, name: Option<&str>) -> zbus::Connection {
+            let mut builder = Builder::address(self.address.as_str()).unwrap();
+            if let Some(name) = name {
+                builder = builder.name(name.to_owned()).unwrap();
+            }
+            builder.build().await.unwrap()
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// Sends what udisks2's object manager does when `path` comes or goes
+    async fn emit(udisks: &zbus::Connection, signal: &str, path: &str) {
+        let path = ObjectPath::try_from(path).unwrap();
+        let manager = "/org/freedesktop/UDisks2";
+        let interface = "org.freedesktop.DBus.ObjectManager";
+        let block = "org.freedesktop.UDisks2.Block";
+        let result = if signal == "InterfacesAdded" {
+            let interfaces: HashMap<&str, HashMap<&str, Value>> =
+                HashMap::from([(block, HashMap::new())]);
+            udisks
+                .emit_signal(None::<()>, manager, interface, signal, &(path, interfaces))
+                .await
+        } else {
+            udisks
+                .emit_signal(None::<()>, manager, interface, signal, &(path, vec![block]))
+                .await
+        };
+        result.unwrap();
+    }
+
+    /// Whether `changes` fires within a moment
+    async fn fired(changes: &mut (impl Stream<Item = ()> + Unpin)) -> bool {
+        tokio::time::timeout(Duration::from_millis(500), changes.next())
+            .await
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn fires_for_block_devices_only() {
+        let Some(bus) = Bus::start() else {
+            return;
+        };
+        let udisks = bus.connect(Some("org.freedesktop.UDisks2")).await;
+        let connection = bus.connect(None).await;
+        let mut changes = Box::pin(changes(&connection).await.unwrap());
+        let sdb = "/org/freedesktop/UDisks2/block_devices/sdb";
+        emit(&udisks, "InterfacesAdded", sdb).await;
+        assert!(fired(&mut changes).await);
+        assert!(!fired(&mut changes).await);
+        let drive = "/org/freedesktop/UDisks2/drives/SanDisk_Cruzer_Glide_4C53000";
+        let job = "/org/freedesktop/UDisks2/jobs/1";
+        emit(&udisks, "InterfacesAdded", drive).await;
+        emit(&udisks, "InterfacesAdded", job).await;
+        emit(&udisks, "InterfacesRemoved", job).await;
+        emit(&udisks, "InterfacesRemoved", drive).await;
+        assert!(!fired(&mut changes).await);
+        emit(&udisks, "InterfacesRemoved", sdb).await;
+        assert!(fired(&mut changes).await);
+        assert!(!fired(&mut changes).await);
+    }
+}
@@ -1,16 +1,33 @@
 use crate::disk::BlockDevice;
+use crate::disk::sysfs::{Sysfs, SysfsDisk};
 use anyhow::Result;
-use humansize::{DECIMAL, format_size};
 
 pub fn get_external_disks() -> Result<Vec<BlockDevice>> {
-    let all_devices = blockdev::get_devices()?;
-    Ok(all_devices
+    Ok(Sysfs::default()
+        .external_disks()?
         .into_iter()
-        // .filter(|d| d.is_disk() && !d.is_mounted())
-        .map(|d| BlockDevice {
-            os_identifier: d.name.clone(),
-            name: d.name,
-            size: format_size(d.size, DECIMAL),
-        })
+        .map(block_device)
         .collect())
 }
+
+/// The disk with the device number `rdev`, as it is now
+pub fn disk_of_rdev(rdev: u64) -> Result<Option<BlockDevice>> {
+    let dev = format!("{}:{}", libc::major(rdev), libc::minor(rdev));
+    Ok(Sysfs::default().disk_by_dev(&dev)?.map(block_device))
+}
+
+fn block_device(d: SysfsDisk) -> BlockDevice {
+    BlockDevice {
+        os_identifier: d.name,
+        id: d.id,
+        size: d.size,
+        vendor: d.vendor,
+        model: d.model,
+        serial: d.serial,
+        transport: d.transport,
+        removable: d.removable,
+        read_only: d.read_only,
+        system: d.system,
+        partitions: d.partitions,
+    }
+}
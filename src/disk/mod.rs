@@ -1,15 +1,114 @@
+use crate::error::Error;
 use anyhow::Result;
+use futures::{Stream, StreamExt, stream};
+use std::{fmt::Display, path::PathBuf, time::Duration};
 
 #[cfg(target_os = "macos")]
 pub mod diskutil;
 #[cfg(target_os = "linux")]
+mod hotplug;
+#[cfg(target_os = "linux")]
 mod lsblk;
+#[cfg(target_os = "linux")]
+mod sysfs;
+#[cfg(target_os = "linux")]
+mod unmount;
+
+/// Objects of udisks2 for disks and partitions, drives and jobs live elsewhere
+#[cfg(target_os = "linux")]
+const UDISKS_BLOCK_DEVICES: &str = "/org/freedesktop/UDisks2/block_devices/";
 
-#[derive(Debug, Clone, Hash)]
+/// A whole disk, with what the system knows about it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BlockDevice {
-    pub name: String,
-    pub size: String,
+    /// Name the system gives the disk, like sda or disk4, which another disk can get once
+    /// this one is unplugged
+    pub os_identifier: String,
+    /// Stays the same while the disk is plugged in, and on Linux also when it is plugged in
+    /// again.
+    ///
+    /// On macOS it is only the identifier, name and size, which two sticks of the same model
+    /// share, so swapping one for the other under the same identifier goes unnoticed.
+    pub id: String,
+    /// In bytes
+    pub size: u64,
+    pub vendor: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub transport: Transport,
+    pub removable: bool,
+    pub read_only: bool,
+    /// Holds the running system, and must never be written to
+    pub system: bool,
+    /// A disk without a partition table, but with a file system, is its own partition
+    pub partitions: Vec<Partition>,
+}
+
+impl BlockDevice {
+    /// Vendor and model, or the identifier when neither is known
+    pub fn name(&self) -> String {
+        let name: Vec<&str> = [&self.vendor, &self.model]
+            .into_iter()
+            .flatten()
+            .map(|s| s.as_str())
+            .collect();
+        if name.is_empty() {
+            self.os_identifier.clone()
+        } else {
+            name.join(" ")
+        }
+    }
+
+    /// Partitions that are mounted or unlocked, which keep the disk from being opened
+    pub fn partitions_in_use(&self) -> impl Iterator<Item = &Partition> {
+        self.partitions
+            .iter()
+            .filter(|partition| !partition.mountpoints.is_empty() || !partition.holders.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Partition {
+    /// Like sda1 or disk4s1
     pub os_identifier: String,
+    /// In bytes
+    pub size: u64,
+    pub label: Option<String>,
+    /// Type of the file system, like vfat or apfs
+    pub filesystem: Option<String>,
+    /// Where the file system, or the one unlocked from it, is mounted
+    pub mountpoints: Vec<PathBuf>,
+    /// Devices built on it, like the unlocked LUKS container dm-0
+    pub holders: Vec<String>,
+}
+
+/// Bus a disk is attached through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Usb,
+    /// SD cards
+    Mmc,
+    /// PCIe hot plugged through a Thunderbolt port, like NVMe enclosures
+    Thunderbolt,
+    Nvme,
+    Ata,
+    Virtio,
+    Unknown,
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Transport::Usb => "USB",
+            Transport::Mmc => "SD card",
+            Transport::Thunderbolt => "Thunderbolt",
+            Transport::Nvme => "NVMe",
+            Transport::Ata => "SATA",
+            Transport::Virtio => "Virtio",
+            Transport::Unknown => "Unknown bus",
+        };
+        write!(f, "{name}")
+    }
 }
 
 #[cfg(target_os = "macos")]
@@ -26,15 +125,77 @@ pub async fn get_external_disks() -> Result<Vec<BlockDevice>> {
         .unwrap()
 }
 
+/// Plugging in a disk adds it and its partitions one by one, at most this far apart
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// The external disks, listed again each time one is plugged in or unplugged
+pub fn watch_external_disks() -> impl Stream<Item = Result<Vec<BlockDevice>>> {
+    stream::once(disk_changes()).flat_map(|changes| match changes {
+        Ok(changes) => settle(changes)
+            .then(|()| get_external_disks())
+            .left_stream(),
+        Err(e) => stream::once(async { Err(e) }).right_stream(),
+    })
+}
+
+#[cfg(target_os = "macos")]
+async fn disk_changes() -> Result<impl Stream<Item = ()>> {
+    diskutil::changes()
+}
+
+#[cfg(target_os = "linux")]
+async fn disk_changes() -> Result<impl Stream<Item = ()>> {
+    hotplug::changes(&udisks2::zbus::Connection::system().await?).await
+}
+
+/// Fires once for a burst of changes, when no more came for `SETTLE`
+fn settle(changes: impl Stream<Item = ()>) -> impl Stream<Item = ()> {
+    stream::unfold(Box::pin(changes), |mut changes| async move {
+        changes.next().await?;
+        while let Ok(Some(())) = tokio::time::timeout(SETTLE, changes.next()).await {}
+        Some(((), changes))
+    })
+}
+
+/// Unmounts the file systems on `device`, which would keep it from being opened
+#[cfg(target_os = "macos")]
+pub async fn unmount_disk(device: &BlockDevice) -> Result<()> {
+    let identifier = device.os_identifier.clone();
+    tokio::task::spawn_blocking(move || diskutil::unmount_disk(&identifier))
+        .await
+        .unwrap()
+}
+
+/// Unmounts the file systems on `device`, and locks its LUKS containers, which would keep
+/// it from being opened
+#[cfg(target_os = "linux")]
+pub async fn unmount_disk(device: &BlockDevice) -> Result<()> {
+    unmount::unmount_disk(device).await
+}
+
+/// Checks that `file` is still open on `device`, and not on a disk that took its place.
+///
+/// On macOS this is only a best effort, it catches a different kind of disk taking the
+/// identifier, but not another stick of the same model and size.
+pub async fn check_device(file: &tokio::fs::File, device: &BlockDevice) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let rdev = file.metadata().await?.rdev();
+    #[cfg(target_os = "linux")]
+    let current = tokio::task::spawn_blocking(move || lsblk::disk_of_rdev(rdev));
+    #[cfg(target_os = "macos")]
+    let current = tokio::task::spawn_blocking(move || diskutil::disk_of_rdev(rdev));
+    match current.await.unwrap()? {
+        Some(current) if current.id == device.id => Ok(()),
+        _ => Err(Error::DeviceChanged(device.name()).into()),
+    }
+}
+
 #[cfg(target_os = "linux")]
 pub async fn get_fd_for_disk(b: BlockDevice) -> Result<tokio::fs::File> {
     use std::{collections::HashMap, os::fd::OwnedFd};
     use udisks2::zbus::zvariant;
     let client = udisks2::Client::new().await?;
-    let object = client.object(format!(
-        "/org/freedesktop/UDisks2/block_devices/{}",
-        b.os_identifier
-    ))?;
+    let object = client.object(format!("{UDISKS_BLOCK_DEVICES}{}", b.os_identifier))?;
     let block = object.block().await?;
     let fd = block
         .open_device(
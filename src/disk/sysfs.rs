@@ -0,0 +1,562 @@
+use crate::disk::{Partition, Transport};
+use anyhow::{Context, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Mountpoints of the running system, the disks under them are never written to
+const SYSTEM_MOUNTPOINTS: &[&str] = &["/", "/boot", "/boot/efi", "/efi"];
+
+/// A whole disk, as the kernel sees it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SysfsDisk {
+    /// Kernel name, like sda
+    pub name: String,
+    /// Stays the same when the disk is plugged in again, like its `/dev/disk/by-id` path
+    pub id: String,
+    /// In bytes
+    pub size: u64,
+    pub vendor: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub removable: bool,
+    pub read_only: bool,
+    pub transport: Transport,
+    pub partitions: Vec<Partition>,
+    /// Holds the root or boot file system of the running system
+    pub system: bool,
+}
+
+impl SysfsDisk {
+    /// Plugged into the computer, instead of built into it
+    pub fn is_external(&self) -> bool {
+        self.removable
+            || matches!(
+                self.transport,
+                Transport::Usb | Transport::Mmc | Transport::Thunderbolt
+            )
+    }
+}
+
+/// What udev found out about a disk or partition
+struct Udev {
+    properties: HashMap<String, String>,
+    /// Symlinks to it under /dev, like disk/by-id/usb-SanDisk_Cruzer_Glide_4C53000-0:0
+    links: Vec<String>,
+}
+
+/// A line of the mount table
+struct Mount {
+    /// major:minor of the mounted device
+    dev: String,
+    mountpoint: PathBuf,
+    /// Device the file system was mounted from, as given to mount
+    source: String,
+}
+
+/// Where disks are looked up, which is the real sysfs, mount table and udev database unless
+/// they are faked
+#[derive(Debug, Clone)]
+pub struct Sysfs {
+    root: PathBuf,
+    mountinfo: PathBuf,
+    udev: PathBuf,
+}
+
+impl Default for Sysfs {
+    fn default() -> Self {
+        Self::new("/sys", "/proc/self/mountinfo", "/run/udev/data")
+    }
+}
+
+impl Sysfs {
+    pub fn new(
+        root: impl Into<PathBuf>,
+        mountinfo: impl Into<PathBuf>,
+        udev: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            root: root.into(),
+            mountinfo: mountinfo.into(),
+            udev: udev.into(),
+        }
+    }
+
+    /// Every disk backed by hardware, without loop, zram, device mapper and other virtual
+    /// devices
+    pub fn disks(&self) -> Result<Vec<SysfsDisk>> {
+        let mounts = self.mounts()?;
+        let system = self.system_disks(&mounts);
+        let mut disks = vec![];
+        for name in list(&self.root.join("block")).context("Failed to list disks")? {
+            let dir = self.block(&name);
+            let path = fs::canonicalize(&dir).unwrap_or_default();
+            if !dir.join("device").exists() || path.to_string_lossy().contains("/virtual/") {
+                continue;
+            }
+            let udev = self.udev(&dir);
+            let device = dir.join("device");
+            let mut partitions: Vec<Partition> = partitions(&dir)
+                .map(|part| self.partition(&dir.join(&part), part, &mounts))
+                .collect();
+            partitions.sort_by(|a, b| a.os_identifier.cmp(&b.os_identifier));
+            // Without a partition table the file system is on the disk itself
+            if partitions.is_empty() && udev.properties.contains_key("ID_FS_TYPE") {
+                partitions.push(self.partition(&dir, name.clone(), &mounts));
+            }
+            disks.push(SysfsDisk {
+                size: size(&dir),
+                vendor: attribute(&device.join("vendor"))
+                    // Virtio only has the PCI ID of its vendor
+                    .filter(|vendor| !vendor.starts_with("0x"))
+                    .or_else(|| udev_string(&udev.properties, "ID_VENDOR")),
+                // SD cards only have a name
+                model: attribute(&device.join("model"))
+                    .or_else(|| attribute(&device.join("name")))
+                    .or_else(|| udev_string(&udev.properties, "ID_MODEL")),
+                serial: udev
+                    .properties
+                    .get("ID_SERIAL_SHORT")
+                    .filter(|serial| !serial.is_empty())
+                    .cloned()
+                    .or_else(|| attribute(&device.join("serial"))),
+                removable: read(&dir.join("removable")) == "1",
+                read_only: read(&dir.join("ro")) == "1",
+                transport: self.transport(&name),
+                partitions,
+                system: system.contains(&name),
+                id: stable_id(&dir, &udev, &name),
+                name,
+            });
+        }
+        disks.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(disks)
+    }
+
+    /// Disks plugged into the computer, apart from any the running system is on
+    pub fn external_disks(&self) -> Result<Vec<SysfsDisk>> {
+        Ok(self
+            .disks()?
+            .into_iter()
+            .filter(|d| d.is_external() && !d.system)
+            .collect())
+    }
+
+    /// The disk numbered `dev`, like 8:16, if there is one
+    pub fn disk_by_dev(&self, dev: &str) -> Result<Option<SysfsDisk>> {
+        let Ok(path) = fs::canonicalize(self.root.join("dev/block").join(dev)) else {
+            return Ok(None);
+        };
+        let name = path.file_name().map(|name| name.to_string_lossy());
+        Ok(self
+            .disks()?
+            .into_iter()
+            .find(|disk| Some(disk.name.as_str()) == name.as_deref()))
+    }
+
+    fn partition(&self, dir: &Path, name: String, mounts: &[Mount]) -> Partition {
+        let udev = self.udev(dir).properties;
+        let devs = self.holders(dir);
+        Partition {
+            os_identifier: name,
+            size: size(dir),
+            label: udev_string(&udev, "ID_FS_LABEL"),
+            filesystem: udev.get("ID_FS_TYPE").filter(|t| !t.is_empty()).cloned(),
+            mountpoints: mounts
+                .iter()
+                .filter(|mount| devs.contains(&mount.dev))
+                .map(|mount| mount.mountpoint.clone())
+                .collect(),
+            holders: list(&dir.join("holders")).unwrap_or_default(),
+        }
+    }
+
+    /// major:minor of the partition at `dir`, and of everything unlocked or assembled from it
+    fn holders(&self, dir: &Path) -> HashSet<String> {
+        let mut devs = HashSet::from([read(&dir.join("dev"))]);
+        for holder in list(&dir.join("holders")).unwrap_or_default() {
+            devs.extend(self.holders(&self.block(&holder)));
+        }
+        devs
+    }
+
+    fn udev(&self, dir: &Path) -> Udev {
+        let data = fs::read_to_string(self.udev.join(format!("b{}", read(&dir.join("dev")))))
+            .unwrap_or_default();
+        Udev {
+            properties: data
+                .lines()
+                .filter_map(|line| line.strip_prefix("E:")?.split_once('='))
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+            links: data
+                .lines()
+                .filter_map(|line| line.strip_prefix("S:"))
+                .map(|link| link.to_owned())
+                .collect(),
+        }
+    }
+
+    fn block(&self, name: &str) -> PathBuf {
+        self.root.join("block").join(name)
+    }
+
+    /// The path of the device has every bus it is attached through
+    fn transport(&self, name: &str) -> Transport {
+        let device = fs::canonicalize(self.block(name).join("device")).unwrap_or_default();
+        let path = device.to_string_lossy();
+        if path.contains("/usb") {
+            Transport::Usb
+        } else if path.contains("/mmc_host/") || name.starts_with("mmcblk") {
+            Transport::Mmc
+        } else if name.starts_with("nvme") {
+            // The kernel marks PCIe ports that devices are hot plugged into
+            let hot_plugged = device
+                .ancestors()
+                .any(|dir| read(&dir.join("removable")) == "removable");
+            if hot_plugged {
+                Transport::Thunderbolt
+            } else {
+                Transport::Nvme
+            }
+        } else if path.contains("/ata") {
+            Transport::Ata
+        } else if path.contains("/virtio") {
+            Transport::Virtio
+        } else {
+            Transport::Unknown
+        }
+    }
+
+    fn mounts(&self) -> Result<Vec<Mount>> {
+        let mountinfo = fs::read_to_string(&self.mountinfo).context("Failed to read mounts")?;
+        let mut mounts = vec![];
+        for line in mountinfo.lines() {
+            // ID, parent ID, major:minor, root, mountpoint, options... - type, source, options
+            let (mount, source) = line.split_once(" - ").unwrap_or((line, ""));
+            let mount: Vec<&str> = mount.split(' ').collect();
+            let (Some(dev), Some(mountpoint)) = (mount.get(2), mount.get(4)) else {
+                continue;
+            };
+            mounts.push(Mount {
+                dev: (*dev).to_owned(),
+                mountpoint: PathBuf::from(unescape_octal(mountpoint)),
+                source: source.split(' ').nth(1).unwrap_or_default().to_owned(),
+            });
+        }
+        Ok(mounts)
+    }
+
+    /// Disks holding the root or boot file system
+    fn system_disks(&self, mounts: &[Mount]) -> HashSet<String> {
+        let mut disks = HashSet::new();
+        for mount in mounts {
+            if !SYSTEM_MOUNTPOINTS
+                .iter()
+                .any(|system| mount.mountpoint == Path::new(system))
+            {
+                continue;
+            }
+            let mut found = self.disks_of_dev(&mount.dev);
+            // Btrfs has its own device numbers, so its source has to do
+            if found.is_empty() && mount.source.starts_with("/dev/") {
+                let source = &mount.source;
+                let source = fs::canonicalize(source).unwrap_or(PathBuf::from(source));
+                if let Some(name) = source.file_name().and_then(|name| name.to_str())
+                    && let Some(disk) = self.disk_of(name)
+                {
+                    found = self.backing(&disk);
+                }
+            }
+            disks.extend(found);
+        }
+        disks
+    }
+
+    /// Disks under the disk or partition numbered `dev`
+    fn disks_of_dev(&self, dev: &str) -> HashSet<String> {
+        for disk in list(&self.root.join("block")).unwrap_or_default() {
+            let dir = self.block(&disk);
+            if read(&dir.join("dev")) == dev
+                || partitions(&dir).any(|part| read(&dir.join(part).join("dev")) == dev)
+            {
+                return self.backing(&disk);
+            }
+        }
+        HashSet::new()
+    }
+
+    /// Disk that the disk or partition `name` is on
+    fn disk_of(&self, name: &str) -> Option<String> {
+        if self.block(name).exists() {
+            return Some(name.to_owned());
+        }
+        list(&self.root.join("block"))
+            .ok()?
+            .into_iter()
+            .find(|disk| self.block(disk).join(name).join("partition").exists())
+    }
+
+    /// Disks that `disk` is stored on, through device mapper or RAID
+    fn backing(&self, disk: &str) -> HashSet<String> {
+        let slaves = list(&self.block(disk).join("slaves")).unwrap_or_default();
+        if slaves.is_empty() {
+            return HashSet::from([disk.to_owned()]);
+        }
+        slaves
+            .iter()
+            .filter_map(|slave| self.disk_of(slave))
+            .flat_map(|disk| self.backing(&disk))
+            .collect()
+    }
+}
+
+/// The `/dev/disk/by-id` path of the disk at `dir`, preferring its WWN.
+///
+/// Without udev, the WWN or serial number the kernel has is used instead, and the kernel
+/// name as a last resort.
+fn stable_id(dir: &Path, udev: &Udev, name: &str) -> String {
+    let mut by_id: Vec<&String> = udev
+        .links
+        .iter()
+        .filter(|link| link.starts_with("disk/by-id/"))
+        .collect();
+    by_id.sort_by_key(|link| (!link.starts_with("disk/by-id/wwn-"), *link));
+    if let Some(link) = by_id.first() {
+        return format!("/dev/{link}");
+    }
+    let device = dir.join("device");
+    if let Some(wwid) = attribute(&dir.join("wwid")).or_else(|| attribute(&device.join("wwid"))) {
+        return format!("wwid:{wwid}");
+    }
+    match attribute(&device.join("serial")) {
+        Some(serial) => format!("serial:{serial}"),
+        None => name.to_owned(),
+    }
+}
+
+/// Partitions are the subdirectories with a partition number
+fn partitions(disk: &Path) -> impl Iterator<Item = String> {
+    list(disk)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(move |name| disk.join(name).join("partition").exists())
+}
+
+fn list(dir: &Path) -> std::io::Result<Vec<String>> {
+    fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect()
+}
+
+/// Contents of an attribute, empty when it is missing
+fn read(path: &Path) -> String {
+    fs::read_to_string(path)
+        .map(|s| s.trim().to_owned())
+        .unwrap_or_default()
+}
+
+/// Contents of an attribute, if it has any
+fn attribute(path: &Path) -> Option<String> {
+    Some(read(path)).filter(|s| !s.is_empty())
+}
+
+/// Always in 512 byte sectors, whatever the disk uses
+fn size(dir: &Path) -> u64 {
+    read(&dir.join("size")).parse::<u64>().unwrap_or(0) * 512
+}
+
+/// udev replaces spaces and other unsafe characters in `key`, but keeps them as `\xNN` in
+/// the `_ENC` version
+fn udev_string(properties: &HashMap<String, String>, key: &str) -> Option<String> {
+    let value = match properties.get(&format!("{key}_ENC")) {
+        Some(encoded) => unescape(encoded, "\\x", 2, 16),
+        None => properties.get(key)?.clone(),
+    };
+    Some(value.trim().to_owned()).filter(|s| !s.is_empty())
+}
+
+/// The mount table writes spaces and the like as `\NNN`
+fn unescape_octal(s: &str) -> String {
+    unescape(s, "\\", 3, 8)
+}
+
+/// Replaces `prefix` followed by `len` digits in `radix` with the byte they encode
+fn unescape(s: &str, prefix: &str, len: usize, radix: u32) -> String {
+    let mut bytes = vec![];
+    let mut rest = s;
+    while let Some(i) = rest.find(prefix) {
+        bytes.extend_from_slice(&rest.as_bytes()[..i]);
+        let after = &rest[i + prefix.len()..];
+        match after
+            .get(..len)
+            .and_then(|digits| u8::from_str_radix(digits, radix).ok())
+        {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &after[len..];
+            }
+            None => {
+                bytes.extend_from_slice(prefix.as_bytes());
+                rest = after;
+            }
+        }
+    }
+    bytes.extend_from_slice(rest.as_bytes());
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    const USB: &str = "pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host0/target0:0:0/0:0:0:0";
+    const NVME: &str = "pci0000:00/0000:00:1d.0/0000:02:00.0/nvme/nvme0";
+
+    /// A sysfs tree, mount table and udev database in a temporary directory
+    struct FakeSysfs {
+        dir: TempDir,
+    }
+
+    impl FakeSysfs {
+        fn new() -> Self {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir_all(dir.path().join("sys/block")).unwrap();
+            fs::create_dir_all(dir.path().join("udev")).unwrap();
+            fs::write(dir.path().join("mountinfo"), "").unwrap();
+            Self { dir }
+        }
+
+        /// Adds the disk `name`, attached through `device` under `/sys/devices`, or a
+        /// virtual one
+        fn disk(&self, name: &str, dev: &str, device: Option<&str>) -> PathBuf {
+            let devices = self.dir.path().join("sys/devices");
+            let dir = devices
+                .join(device.unwrap_or("virtual"))
+                .join("block")
+                .join(name);
+            fs::create_dir_all(&dir).unwrap();
+            let attributes = [
+                ("dev", dev),
+                ("size", "2048"),
+                ("removable", "0"),
+                ("ro", "0"),
+            ];
+            for (attribute, value) in attributes {
+                fs::write(dir.join(attribute), value).unwrap();
+            }
+            if let Some(device) = device {
+                symlink(devices.join(device), dir.join("device")).unwrap();
+            }
+            symlink(&dir, self.dir.path().join("sys/block").join(name)).unwrap();
+            dir
+        }
+
+        fn partition(&self, disk: &Path, name: &str, dev: &str) -> PathBuf {
+            let dir = disk.join(name);
+            fs::create_dir_all(&dir).unwrap();
+            for (attribute, value) in [("partition", "1"), ("dev", dev), ("size", "1024")] {
+                fs::write(dir.join(attribute), value).unwrap();
+            }
+            dir
+        }
+
+        fn mount(&self, dev: &str, mountpoint: &str, source: &str) {
+            let path = self.dir.path().join("mountinfo");
+            let mut mountinfo = fs::read_to_string(&path).unwrap();
+            mountinfo.push_str(&format!(
+                "36 1 {dev} / {mountpoint} rw - ext4 {source} rw\n"
+            ));
+            fs::write(path, mountinfo).unwrap();
+        }
+
+        fn sysfs(&self) -> Sysfs {
+            let root = self.dir.path();
+            Sysfs::new(root.join("sys"), root.join("mountinfo"), root.join("udev"))
+        }
+
+        fn get(&self, name: &str) -> SysfsDisk {
+            let disks = self.sysfs().disks().unwrap();
+            disks.into_iter().find(|disk| disk.name == name).unwrap()
+        }
+
+        fn external(&self) -> Vec<String> {
+            let disks = self.sysfs().external_disks().unwrap();
+            disks.into_iter().map(|disk| disk.name).collect()
+        }
+    }
+
+    #[test]
+    fn usb_disk_is_kept() {
+        let sys = FakeSysfs::new();
+        sys.disk("sdb", "8:16", Some(USB));
+        assert_eq!(sys.external(), ["sdb"]);
+        let disk = sys.get("sdb");
+        assert_eq!(disk.transport, Transport::Usb);
+        assert_eq!(disk.size, 2048 * 512);
+    }
+
+    #[test]
+    fn loop_and_zram_are_hidden() {
+        let sys = FakeSysfs::new();
+        sys.disk("loop0", "7:0", None);
+        sys.disk("zram0", "252:0", None);
+        sys.disk("sdb", "8:16", Some(USB));
+        let disks = sys.sysfs().disks().unwrap();
+        let names: Vec<&str> = disks.iter().map(|disk| disk.name.as_str()).collect();
+        assert_eq!(names, ["sdb"]);
+        assert_eq!(sys.external(), ["sdb"]);
+    }
+
+    #[test]
+    fn internal_nvme_is_hidden() {
+        let sys = FakeSysfs::new();
+        sys.disk("nvme0n1", "259:0", Some(NVME));
+        assert_eq!(sys.get("nvme0n1").transport, Transport::Nvme);
+        assert!(sys.external().is_empty());
+    }
+
+    #[test]
+    fn disk_mounted_on_root_is_system() {
+        let sys = FakeSysfs::new();
+        let disk = sys.disk("sdb", "8:16", Some(USB));
+        sys.partition(&disk, "sdb1", "8:17");
+        sys.disk("sdc", "8:32", Some(USB));
+        sys.mount("8:17", "/", "/dev/sdb1");
+        let sdb = sys.get("sdb");
+        assert!(sdb.system);
+        assert_eq!(sdb.partitions[0].mountpoints, [PathBuf::from("/")]);
+        assert!(!sys.get("sdc").system);
+        assert_eq!(sys.external(), ["sdc"]);
+    }
+
+    #[test]
+    fn disk_under_luks_root_is_system() {
+        let sys = FakeSysfs::new();
+        let disk = sys.disk("sdb", "8:16", Some(USB));
+        let partition = sys.partition(&disk, "sdb2", "8:18");
+        fs::create_dir_all(partition.join("holders/dm-0")).unwrap();
+        let dm = sys.disk("dm-0", "254:0", None);
+        fs::create_dir_all(dm.join("slaves/sdb2")).unwrap();
+        sys.mount("254:0", "/", "/dev/mapper/root");
+        let sdb = sys.get("sdb");
+        assert!(sdb.system);
+        assert_eq!(sdb.partitions[0].holders, ["dm-0"]);
+        assert_eq!(sdb.partitions[0].mountpoints, [PathBuf::from("/")]);
+        assert!(sys.external().is_empty());
+    }
+
+    #[test]
+    fn read_only_flag() {
+        let sys = FakeSysfs::new();
+        let disk = sys.disk("sdb", "8:16", Some(USB));
+        fs::write(disk.join("ro"), "1\n").unwrap();
+        sys.disk("sdc", "8:32", Some(USB));
+        assert!(sys.get("sdb").read_only);
+        assert!(!sys.get("sdc").read_only);
+    }
+}
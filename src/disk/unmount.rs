@@ -0,0 +1,79 @@
+use crate::disk::{BlockDevice, UDISKS_BLOCK_DEVICES};
+use crate::error::Error;
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+use udisks2::{Client, Object};
+
+/// Unmounts the file systems on `device`, and locks the LUKS containers on it, so it can be
+/// opened exclusively
+pub async fn unmount_disk(device: &BlockDevice) -> Result<()> {
+    let client = Client::new().await?;
+    for partition in &device.partitions {
+        let object = client.object(format!("{UDISKS_BLOCK_DEVICES}{}", partition.os_identifier))?;
+        if let Ok(encrypted) = object.encrypted().await {
+            let cleartext = encrypted.cleartext_device().await?;
+            // Locked containers have no cleartext device
+            if cleartext.as_str() != "/" {
+                unmount(&client.object(cleartext)?).await?;
+                encrypted
+                    .lock(HashMap::new())
+                    .await
+                    .with_context(|| format!("Failed to lock {}", partition.os_identifier))?;
+            }
+        }
+        unmount(&object).await?;
+    }
+    Ok(())
+}
+
+async fn unmount(object: &Object) -> Result<()> {
+    let Ok(filesystem) = object.filesystem().await else {
+        return Ok(());
+    };
+    // Each call takes off one of its mounts
+    for mountpoint in filesystem.mount_points().await? {
+        let mountpoint = mountpoint.strip_suffix(&[0]).unwrap_or(&mountpoint);
+        let mountpoint = PathBuf::from(OsStr::from_bytes(mountpoint));
+        match filesystem.unmount(HashMap::new()).await {
+            Ok(()) | Err(udisks2::Error::NotMounted) => {}
+            Err(udisks2::Error::DeviceBusy) => {
+                let users = users_of(&mountpoint);
+                return Err(Error::MountBusy(mountpoint, users).into());
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to unmount {}", mountpoint.display()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Processes with a file or their working directory under `mountpoint`, as far as they can
+/// be seen
+fn users_of(mountpoint: &Path) -> Vec<String> {
+    let mut users = vec![];
+    for entry in fs::read_dir("/proc").into_iter().flatten().flatten() {
+        let pid = entry.file_name().to_string_lossy().into_owned();
+        if !pid.bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        let dir = entry.path();
+        let fds = fs::read_dir(dir.join("fd")).into_iter().flatten().flatten();
+        let uses = std::iter::once(dir.join("cwd"))
+            .chain(fds.map(|fd| fd.path()))
+            .filter_map(|link| fs::read_link(link).ok())
+            .any(|target| target.starts_with(mountpoint));
+        if uses {
+            let name = fs::read_to_string(dir.join("comm")).unwrap_or_default();
+            users.push(format!("{} ({pid})", name.trim()));
+        }
+    }
+    users
+}
@@ -1,92 +1,634 @@
+use crate::decompress::{self, TeeReader, ZIP_HEADER_LEN};
+use crate::device_writer::{DeviceWriter, WriterOptions, WrittenMeter};
+use crate::error::Error;
+use crate::fetch::{self, Chunk, PartReader};
+use crate::pipeline::Pipeline;
+use crate::resume::{self, ResumeState};
+use crate::segmented;
+use crate::signature;
+use crate::verify::{HashWriter, Written};
 use anyhow::{Context, Result, anyhow};
-use futures::StreamExt;
+use bytes::Bytes;
 use iced::task::{Straw, sipper};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::IgnoredAny};
 use sha2::{Digest, Sha256};
-use std::sync::Arc;
-use tokio::{
-    fs::File,
-    io::{AsyncWriteExt, BufWriter},
+use sipper::Sender;
+use std::{
+    io::{BufWriter, Seek, SeekFrom},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
 };
+use tokio::fs::File;
 use tokio_util::sync::CancellationToken;
 
 #[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
 pub struct Distro {
     pub name: String,
     iso_compression: Option<CompressionAlgorithim>,
-    pub iso: Vec<String>,
+    pub iso: Vec<IsoPart>,
+    /// Checksum of the downloaded data, before decompression
     sha256: Option<String>,
+    /// Minisign signature of `sha256`, checked before the download starts
+    #[serde(default)]
+    sha256_signature: Option<String>,
+    /// Release of the distro the ISO is for
+    #[serde(default)]
+    pub version: Option<String>,
+    /// As YYYY-MM-DD
+    #[serde(default)]
+    pub release_date: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Total size of the parts, in bytes
+    #[serde(default)]
+    pub download_size: Option<u64>,
+    #[serde(default)]
+    pub architecture: Option<String>,
+    #[serde(default)]
+    pub desktop_environment: Option<String>,
+    /// Homepage or wiki page for the distro
+    #[serde(default)]
+    pub homepage: Option<String>,
+    /// Anything to know before installing, shown before the download starts
+    #[serde(default)]
+    pub install_notes: Option<String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
+#[serde(try_from = "IsoPartRepr")]
+pub struct IsoPart {
+    /// Mirrors of the part, in order of preference
+    pub urls: Vec<String>,
+    /// Checksum of this part alone, as downloaded
+    pub sha256: Option<String>,
+}
+
+/// Parts are either just a url, or one or more mirrors with a checksum
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IsoPartRepr {
+    Url(String),
+    Part {
+        url: Option<String>,
+        #[serde(default)]
+        urls: Vec<String>,
+        sha256: Option<String>,
+    },
+}
+
+impl TryFrom<IsoPartRepr> for IsoPart {
+    type Error = &'static str;
+
+    fn try_from(part: IsoPartRepr) -> Result<Self, Self::Error> {
+        match part {
+            IsoPartRepr::Url(url) => Ok(Self {
+                urls: vec![url],
+                sha256: None,
+            }),
+            IsoPartRepr::Part { url, urls, sha256 } => {
+                let urls: Vec<String> = url.into_iter().chain(urls).collect();
+                if urls.is_empty() {
+                    return Err("ISO part has no urls");
+                }
+                Ok(Self { urls, sha256 })
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
 #[serde(rename_all = "snake_case")]
-enum CompressionAlgorithim {
+pub enum CompressionAlgorithim {
+    /// The image is the first file in the archive.
+    ///
+    /// It is extracted while it is downloaded, so it has to be stored with its length in
+    /// front of it: archives written with a data descriptor (general purpose flag bit 3),
+    /// like most streaming zip writers make, and encrypted ones are refused.
     Zip,
+    Xz,
+    Zstd,
+    Gzip,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadProgress {
+    /// Part, Bytes done and Bytes total of all parts
+    Progress(usize, u64, Option<u64>),
+    /// Part that did not match its checksum, and is downloaded again
+    PartChecksumFailed(usize),
+    /// Part, Mirror it is downloaded from
+    Mirror(usize, String),
+    /// Part, Attempt, Delay before it
+    Retry(usize, usize, Duration),
+    /// Bytes that reached the disk, which can fall behind the download
+    Written(u64),
+}
+
+/// How often the disk is checked on, once the download is done
+const WRITER_POLL: Duration = Duration::from_millis(250);
+
+/// Number of times corrupt parts are downloaded again before giving up
+pub const PART_RETRIES: usize = 3;
+
+/// Newest version of the catalog format that can be read.
+///
+/// 1: Name, parts, compression and checksum.
+/// 2: Per-part checksums and mirrors, information about each release, and signed checksums.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// `T` is ignored to check the version before parsing the distros
 #[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
-struct DistroMetadataWrapper {
-    all: Vec<Distro>,
+struct DistroMetadataWrapper<T = Vec<Distro>> {
+    /// Missing from catalogs older than version 2
+    #[serde(default = "schema_version_1")]
+    schema_version: u32,
+    all: T,
+}
+
+fn schema_version_1() -> u32 {
+    1
 }
 
 impl Distro {
-    pub async fn get_all() -> Result<Vec<Distro>> {
-        let req = reqwest::get("https://wiki.t2linux.org/tools/distro-metadata.json").await?;
-        let iso_metadata = req.bytes().await?;
-        let iso_metadata: DistroMetadataWrapper = serde_json::from_slice(&iso_metadata)?;
+    pub fn parse_all(iso_metadata: &[u8]) -> Result<Vec<Distro>> {
+        let version: DistroMetadataWrapper<Option<IgnoredAny>> =
+            serde_json::from_slice(iso_metadata).context("Failed to parse distro catalog")?;
+        if version.schema_version > SCHEMA_VERSION {
+            return Err(Error::UnsupportedSchema(version.schema_version).into());
+        }
+        let iso_metadata: DistroMetadataWrapper =
+            serde_json::from_slice(iso_metadata).context("Failed to parse distro catalog")?;
         Ok(iso_metadata.all)
     }
 
+    /// Checksum of the downloaded data, which names it in the cache
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+
+    /// Format the download is compressed in
+    pub fn compression(&self) -> Option<&CompressionAlgorithim> {
+        self.iso_compression.as_ref()
+    }
+
+    /// Fails when the archive can't be extracted, which is known from its first bytes, so
+    /// it is found out before the disk is erased
+    pub async fn check_archive(&self) -> Result<()> {
+        if self.iso_compression != Some(CompressionAlgorithim::Zip) {
+            return Ok(());
+        }
+        let Some(part) = self.iso.first() else {
+            return Ok(());
+        };
+        let client = fetch::client()?;
+        let mut reader = PartReader::new(&client, &part.urls, 0, Some(ZIP_HEADER_LEN as u64));
+        let mut header = vec![];
+        while header.len() < ZIP_HEADER_LEN {
+            match reader.next().await {
+                Ok(Some(Chunk::Data(data))) => header.extend_from_slice(&data),
+                Ok(None) => break,
+                // The ISO might be cached, and the download reports mirrors that stay down
+                Ok(Some(Chunk::Retry(_, _))) | Err(_) => return Ok(()),
+            }
+        }
+        decompress::check_zip(&header)?;
+        Ok(())
+    }
+
+    /// Fills in `download_size` from the lengths the servers give for the parts, when the
+    /// catalog has none, so the ISO can be checked to fit on the disk
+    pub async fn probe_size(&mut self) {
+        if self.download_size.is_some() {
+            return;
+        }
+        let Ok(client) = fetch::client() else {
+            return;
+        };
+        self.download_size = segmented::probe(&client, &self.iso)
+            .await
+            .map(|probe| probe.len());
+    }
+
+    pub fn check_signature(&self) -> Result<()> {
+        if let Some(signature) = &self.sha256_signature {
+            let sum = self.sha256().context("Signed ISO has no checksum")?;
+            signature::verify("ISO checksum", sum.as_bytes(), Some(signature))?;
+        }
+        Ok(())
+    }
+
+    /// Downloads the ISO into `file`.
+    ///
+    /// When `resume` is set, `file` is a regular file and uncompressed downloads continue
+    /// from wherever a previous attempt left off, and are fetched in parallel segments when
+    /// the servers allow it. A disk is always written in order, through a [`DeviceWriter`].
+    ///
+    /// Otherwise, the downloaded data is also written into `cache` as it is, when set.
+    ///
+    /// A part that does not match its checksum is downloaded again, from its start, unless
+    /// the ISO is compressed. The decompressor can't go back to the start of the part, so
+    /// compressed downloads fail with [`Error::CompressedPartChecksum`] instead.
     pub fn download_iso(
         &self,
         file: Arc<File>,
+        resume: Option<PathBuf>,
+        cache: Option<std::fs::File>,
         ct: CancellationToken,
-    ) -> impl Straw<(), (usize, f64), anyhow::Error> {
-        let s = self.clone();
-        let mut hasher = Sha256::new();
+    ) -> impl Straw<Written, DownloadProgress, anyhow::Error> {
+        let mut s = self.clone();
+        // Data from before a resume is not downloaded again, so it would be missing
+        let mut cache = cache.filter(|_| resume.is_none());
         sipper(async move |mut sender| {
-            if s.iso_compression.is_some() {
-                return Err(anyhow!("Compression is unimplemented"));
+            s.check_signature()?;
+            let client = fetch::client()?;
+            let mut iso_file = open_target(&file).await?;
+            // Segments land all over the target, which only suits a file
+            let segmentable = s.iso_compression.is_none() && resume.is_some();
+            let probe = if segmentable || s.download_size.is_none() {
+                segmented::probe(&client, &s.iso).await
+            } else {
+                None
             };
-            let client = reqwest::Client::new();
-            let mut iso_file_buf = BufWriter::new(file.try_clone().await?);
-            for (part, url) in s.iso.iter().enumerate() {
-                let request = client
-                    .get(url)
-                    .send()
-                    .await
-                    .with_context(|| format!("Failed to request ISO url: {url}"))?;
-                let total_len = request.content_length();
-                let mut current_len: u64 = 0;
-                let mut data = request.bytes_stream();
-                while let Some(Ok(data)) = data.next().await {
-                    if ct.is_cancelled() {
-                        return Err(anyhow!("Download cancelled"));
-                    };
-                    iso_file_buf
-                        .write_all(&data)
-                        .await
-                        .context("Failed to write to iso download location")?;
-                    hasher.update(&data);
-                    current_len += data.len() as u64;
-                    if let Some(total_len) = total_len {
-                        sender
-                            .send((part + 1, (current_len as f64) / (total_len as f64)))
-                            .await;
-                    } else {
-                        sender.send((part + 1, 0.0)).await;
+            // Without a size in the catalog, the parts' lengths make up the total
+            s.download_size = s
+                .download_size
+                .or(probe.as_ref().map(segmented::Probe::len));
+            let segment_lens = probe
+                .filter(|probe| segmentable && probe.ranges)
+                .map(|probe| probe.part_lens);
+            let mut resume_state = None;
+            if let Some(path) = resume {
+                match ResumeState::load(path.clone(), &s.iso).await {
+                    Some(state)
+                        if s.iso_compression.is_none()
+                            && state.is_segmented() == segment_lens.is_some() =>
+                    {
+                        resume_state = Some(state)
+                    }
+                    _ => {
+                        iso_file.set_len(0)?;
+                        if s.iso_compression.is_none() {
+                            resume_state = Some(ResumeState::new(path, &s.iso));
+                        }
                     }
                 }
-                iso_file_buf.flush().await?;
             }
-            let sha256sum = hasher.finalize();
-            if let Some(orig_sum) = s.sha256 {
-                let orig_sum = hex::decode(orig_sum).context("Could not decode checksum")?;
-                if sha256sum.as_slice() != orig_sum {
-                    return Err(anyhow!("Checksums do not match"));
-                };
+            let (hasher, written) = if let Some(part_lens) = segment_lens {
+                let iso_file = segmented::download(
+                    &client,
+                    &s.iso,
+                    &part_lens,
+                    segmented::Output {
+                        file: iso_file,
+                        cache,
+                    },
+                    resume_state.as_mut(),
+                    &ct,
+                    &mut sender,
+                )
+                .await?;
+                let len = part_lens.iter().sum();
+                let hasher = resume::hash_range(Arc::new(iso_file), 0, len, Sha256::new()).await?;
+                let sha256 = hasher.clone().finalize();
+                (hasher, Written { len, sha256 })
+            } else {
+                // Where each part starts is needed to download it again
+                if s.iso_compression.is_none() && resume_state.is_none() {
+                    resume_state = Some(ResumeState::in_memory(&s.iso));
+                }
+                let mut retries = 0;
+                loop {
+                    let res = download_sequential(
+                        &s,
+                        &client,
+                        iso_file,
+                        cache.as_ref().map(std::fs::File::try_clone).transpose()?,
+                        resume_state.as_mut(),
+                        &ct,
+                        &mut sender,
+                    )
+                    .await;
+                    match (res, &resume_state) {
+                        (Ok(res), _) => break res,
+                        // Uncompressed data can be rewound to the start of the part
+                        (Err(e), Some(state)) if retries < PART_RETRIES => {
+                            let Some(Error::PartChecksum(part)) = e.downcast_ref() else {
+                                return Err(e);
+                            };
+                            retries += 1;
+                            sender
+                                .send(DownloadProgress::PartChecksumFailed(*part))
+                                .await;
+                            iso_file = file.try_clone().await?.into_std().await;
+                            if iso_file.metadata()?.is_file() {
+                                iso_file.set_len(state.written())?;
+                            }
+                            if let Some(cache) = &mut cache {
+                                cache.set_len(state.written())?;
+                                cache.seek(SeekFrom::Start(state.written()))?;
+                            }
+                        }
+                        (Err(e), _) => return Err(e),
+                    }
+                }
+            };
+            if let Some(orig_sum) = &s.sha256
+                && !sha256_matches(orig_sum, hasher)?
+            {
+                // Resuming would only hash the same data again, so the next attempt starts over
+                if let Some(state) = resume_state {
+                    state.remove().await?;
+                }
+                if file.metadata().await?.is_file() {
+                    file.set_len(0).await?;
+                }
+                return Err(anyhow!("Checksums do not match"));
             };
-            Ok(())
+            if let Some(state) = resume_state {
+                state.remove().await?;
+            }
+            Ok(written)
         })
     }
 }
+
+pub fn sha256_matches(expected: &str, hasher: Sha256) -> Result<bool> {
+    let expected = hex::decode(expected).context("Could not decode checksum")?;
+    Ok(hasher.finalize().as_slice() == expected)
+}
+
+/// Streams each part in turn into `iso_file`, decompressing it on the way.
+///
+/// Returns the hash of all of the downloaded data, and what was written.
+async fn download_sequential(
+    s: &Distro,
+    client: &reqwest::Client,
+    mut iso_file: std::fs::File,
+    cache: Option<std::fs::File>,
+    mut resume_state: Option<&mut ResumeState>,
+    ct: &CancellationToken,
+    sender: &mut Sender<DownloadProgress>,
+) -> Result<(Sha256, Written)> {
+    let mut hasher = Sha256::new();
+    let mut part_hasher = Sha256::new();
+    let (mut start_part, mut start_offset) = (0, 0);
+    let mut done = 0;
+    if let Some(state) = &mut resume_state {
+        let metadata = iso_file.metadata()?;
+        // A disk has no length to go by, so it continues after the last whole part
+        let written = match metadata.is_file() {
+            true => metadata.len(),
+            false => state.written(),
+        };
+        done = written;
+        (start_part, start_offset) = state.position(written);
+        let file = Arc::new(iso_file.try_clone()?);
+        hasher = resume::hash_range(file.clone(), 0, written, hasher).await?;
+        part_hasher =
+            resume::hash_range(file, written - start_offset, start_offset, part_hasher).await?;
+        iso_file.seek(SeekFrom::Start(written))?;
+        state.save().await?;
+    }
+    let mut writer = IsoWriter::resume(
+        iso_file,
+        s.iso_compression.clone(),
+        cache,
+        (hasher, part_hasher, done),
+    )?;
+    let mut failed_part = None;
+    'parts: for (part, iso_part) in s.iso.iter().enumerate().skip(start_part) {
+        let offset = if part == start_part { start_offset } else { 0 };
+        let mut reader = PartReader::new(client, &iso_part.urls, offset, None);
+        let mut mirror = None;
+        let mut current_len: u64 = offset;
+        let part_start = done - offset;
+        loop {
+            let chunk = tokio::select! {
+                chunk = reader.next() => chunk,
+                _ = ct.cancelled() => return Err(anyhow!("Download cancelled")),
+            };
+            let data =
+                match chunk.with_context(|| format!("Failed to download part {}", part + 1))? {
+                    Some(Chunk::Data(data)) => data,
+                    Some(Chunk::Retry(attempt, delay)) => {
+                        sender
+                            .send(DownloadProgress::Retry(part + 1, attempt, delay))
+                            .await;
+                        continue;
+                    }
+                    None => break,
+                };
+            if mirror != Some(reader.mirror()) {
+                mirror = Some(reader.mirror());
+                sender
+                    .send(DownloadProgress::Mirror(part + 1, reader.url().to_owned()))
+                    .await;
+            }
+            current_len += data.len() as u64;
+            done += data.len() as u64;
+            let pushed = tokio::select! {
+                pushed = writer.push(data) => pushed,
+                _ = ct.cancelled() => return Err(anyhow!("Download cancelled")),
+            };
+            if !pushed {
+                break 'parts;
+            }
+            // Progress is measured on the compressed stream, as the decompressed size is unknown.
+            // Without a size from the catalog or the servers, the total is only known once the
+            // last part starts.
+            let last_part = part == s.iso.len() - 1;
+            let total = s.download_size.or(reader
+                .len()
+                .filter(|_| last_part)
+                .map(|len| part_start + len));
+            sender
+                .send(DownloadProgress::Progress(part + 1, done, total))
+                .await;
+            writer.report(sender).await;
+        }
+        let part_hasher = writer.end_part().await?;
+        if let Some(sum) = &iso_part.sha256
+            && !sha256_matches(sum, part_hasher)?
+        {
+            failed_part = Some(part + 1);
+            break;
+        }
+        if let Some(state) = &mut resume_state {
+            state.finish_part(current_len);
+            state.save().await?;
+        }
+    }
+    let (hasher, written) = writer.finish(sender).await?;
+    // Decompressing fails when a part is cut off, the mismatch explains that better
+    if let Some(part) = failed_part {
+        return Err(match s.iso_compression {
+            Some(_) => Error::CompressedPartChecksum(part),
+            None => Error::PartChecksum(part),
+        }
+        .into());
+    }
+    Ok((hasher, written?))
+}
+
+/// A clone of `file` to write the ISO into, from its start
+pub async fn open_target(file: &File) -> Result<std::fs::File> {
+    let mut iso_file = file.try_clone().await?.into_std().await;
+    // Clones share the position, which an earlier attempt might have moved
+    iso_file.rewind()?;
+    Ok(iso_file)
+}
+
+/// Writes an ISO into the target as its data arrives, decompressing it through a
+/// [`DeviceWriter`], and hashing it on the way.
+pub struct IsoWriter {
+    pipeline: Pipeline<Written>,
+    meter: WrittenMeter,
+}
+
+impl IsoWriter {
+    /// Starts at the position of `iso_file`, copying the data as it is into `cache` when set
+    pub fn new(
+        iso_file: std::fs::File,
+        compression: Option<CompressionAlgorithim>,
+        cache: Option<std::fs::File>,
+    ) -> Result<Self> {
+        Self::resume(
+            iso_file,
+            compression,
+            cache,
+            (Sha256::new(), Sha256::new(), 0),
+        )
+    }
+
+    /// Continues after `done` bytes, which are already in the hasher and, for the current
+    /// part, in the part hasher
+    pub fn resume(
+        iso_file: std::fs::File,
+        compression: Option<CompressionAlgorithim>,
+        cache: Option<std::fs::File>,
+        (hasher, part_hasher, done): (Sha256, Sha256, u64),
+    ) -> Result<Self> {
+        let device = DeviceWriter::new(iso_file, WriterOptions::from_env())?;
+        let meter = device.meter();
+        // Only uncompressed downloads are resumed, so what was downloaded is what was written
+        let mut iso_file = HashWriter::resume(device, hasher.clone(), done);
+        let pipeline = Pipeline::resume(hasher, part_hasher, move |reader| {
+            decompress::copy(
+                compression.as_ref(),
+                TeeReader::new(reader, cache.map(BufWriter::new)),
+                &mut iso_file,
+            )
+            .map(|_| iso_file.written())
+        });
+        Ok(Self { pipeline, meter })
+    }
+
+    /// Hands `data` to the writer.
+    ///
+    /// Returns false once the writer failed, which [`IsoWriter::finish`] reports.
+    pub async fn push(&mut self, data: Bytes) -> bool {
+        self.pipeline.push(data).await
+    }
+
+    /// Sends how much has reached the disk, if it is time to
+    pub async fn report(&mut self, sender: &mut Sender<DownloadProgress>) {
+        if let Some(written) = self.meter.poll() {
+            sender.send(DownloadProgress::Written(written)).await;
+        }
+    }
+
+    /// Hash of the data pushed since the last call
+    pub async fn end_part(&mut self) -> Result<Sha256> {
+        self.pipeline.end_part().await
+    }
+
+    /// Waits for the rest of the data to reach the disk, reporting how much did in the
+    /// meantime.
+    ///
+    /// Returns the hash of everything pushed, and what was written, or why writing failed.
+    pub async fn finish(
+        mut self,
+        sender: &mut Sender<DownloadProgress>,
+    ) -> Result<(Sha256, Result<Written>)> {
+        let (hasher, mut writer) = self.pipeline.finish().await?;
+        loop {
+            let finished = tokio::select! {
+                res = &mut writer => Some(res?),
+                _ = tokio::time::sleep(WRITER_POLL) => None,
+            };
+            if let Some(written) = self.meter.poll() {
+                sender.send(DownloadProgress::Written(written)).await;
+            }
+            if let Some(res) = finished {
+                let written = res.context("Failed to write to iso download location");
+                return Ok((hasher, written));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v1_catalog_still_parses() {
+        let catalog = br#"{"all": [{
+            "name": "Ubuntu",
+            "iso": ["https://example.com/1.iso", "https://example.com/2.iso"],
+            "iso_compression": "zip",
+            "sha256": "ab"
+        }]}"#;
+        let distros = Distro::parse_all(catalog).unwrap();
+        assert_eq!(distros.len(), 1);
+        let distro = &distros[0];
+        assert_eq!(distro.compression(), Some(&CompressionAlgorithim::Zip));
+        assert_eq!(distro.sha256(), Some("ab"));
+        assert_eq!(
+            distro.iso,
+            [
+                IsoPart {
+                    urls: vec!["https://example.com/1.iso".to_owned()],
+                    sha256: None,
+                },
+                IsoPart {
+                    urls: vec!["https://example.com/2.iso".to_owned()],
+                    sha256: None,
+                },
+            ]
+        );
+        assert_eq!(distro.download_size, None);
+    }
+
+    #[test]
+    fn v2_parts_with_mirrors() {
+        let catalog = br#"{"schema_version": 2, "all": [{
+            "name": "Fedora",
+            "iso": [{"url": "https://a/1.iso", "urls": ["https://b/1.iso"], "sha256": "cd"}],
+            "download_size": 42
+        }]}"#;
+        let distros = Distro::parse_all(catalog).unwrap();
+        assert_eq!(
+            distros[0].iso,
+            [IsoPart {
+                urls: vec!["https://a/1.iso".to_owned(), "https://b/1.iso".to_owned()],
+                sha256: Some("cd".to_owned()),
+            }]
+        );
+        assert_eq!(distros[0].download_size, Some(42));
+        let no_urls = br#"{"all": [{"name": "Fedora", "iso": [{"sha256": "cd"}]}]}"#;
+        assert!(Distro::parse_all(no_urls).is_err());
+    }
+
+    #[test]
+    fn newer_schema_is_refused() {
+        // Checked before anything else, so a changed layout can't be misread
+        let catalog = br#"{"schema_version": 3, "all": {"distros": []}}"#;
+        let e = Distro::parse_all(catalog).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(Error::UnsupportedSchema(3))
+        ));
+    }
+}
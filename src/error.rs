@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -6,6 +7,72 @@ pub enum Error {
     FileWrite(#[from] std::io::Error),
     #[error(transparent)]
     IsoDownload(#[from] anyhow::Error),
+    #[error("part {0} of the ISO does not match its checksum")]
+    PartChecksum(usize),
+    #[error(
+        "part {0} of the ISO does not match its checksum, and a compressed download can only be started over as a whole"
+    )]
+    CompressedPartChecksum(usize),
+    #[error("local copy of the ISO does not match its checksum")]
+    LocalChecksum,
+    #[error("distro catalog uses schema version {0}, which is newer than this installer supports")]
+    UnsupportedSchema(u32),
+    #[error("{0} is not signed, set T2LINUX_ALLOW_UNSIGNED=1 to use it anyway")]
+    Unsigned(String),
+    #[error("{0} can't be checked, as no signing key is built into this installer")]
+    NoTrustedKeys(String),
+    #[error("{0} is signed with a key that is not trusted")]
+    UntrustedKey(String),
+    #[error("signature of {0} is not valid: {1}")]
+    BadSignature(String, String),
+    #[error(
+        "data read back from the disk does not match what was written, the disk might be faulty"
+    )]
+    VerifyMismatch,
     #[error("installation cancelled")]
     Cancelled,
+    #[error("the disk that was opened is no longer {0}, it might have been unplugged")]
+    DeviceChanged(String),
+    /// Mountpoint, and the processes using it that could be found
+    #[error("{} is in use{}, close it and try again", .0.display(), used_by(.1))]
+    MountBusy(PathBuf, Vec<String>),
+    #[error("{0} is smaller than the ISO")]
+    TargetTooSmall(String),
+    #[error("{0} holds the running system")]
+    SystemDisk(String),
+    #[error("{0} is read-only")]
+    ReadOnly(String),
+}
+
+fn used_by(processes: &[String]) -> String {
+    if processes.is_empty() {
+        String::new()
+    } else {
+        format!(" by {}", processes.join(", "))
+    }
+}
+
+impl Error {
+    /// Name of the variant, which other programs can match on
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::FileWrite(_) => "file_write",
+            Error::IsoDownload(_) => "iso_download",
+            Error::PartChecksum(_) => "part_checksum",
+            Error::CompressedPartChecksum(_) => "compressed_part_checksum",
+            Error::LocalChecksum => "local_checksum",
+            Error::UnsupportedSchema(_) => "unsupported_schema",
+            Error::Unsigned(_) => "unsigned",
+            Error::NoTrustedKeys(_) => "no_trusted_keys",
+            Error::UntrustedKey(_) => "untrusted_key",
+            Error::BadSignature(_, _) => "bad_signature",
+            Error::VerifyMismatch => "verify_mismatch",
+            Error::Cancelled => "cancelled",
+            Error::DeviceChanged(_) => "device_changed",
+            Error::MountBusy(_, _) => "mount_busy",
+            Error::TargetTooSmall(_) => "target_too_small",
+            Error::SystemDisk(_) => "system_disk",
+            Error::ReadOnly(_) => "read_only",
+        }
+    }
 }
@@ -0,0 +1,105 @@
+use crate::error::Error;
+use crate::fetch::RETRIES;
+use crate::install::InstallProgress;
+use crate::progress::ByteProgress;
+use serde::Serialize;
+
+/// Progress of the installer, printed for other programs as one JSON object per line.
+///
+/// Each object has an `event` field naming the variant, next to the variant's fields.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Start {
+        source: String,
+        target: String,
+    },
+    /// The ISO is copied from the cache instead of being downloaded
+    Cached,
+    DownloadStart {
+        parts: usize,
+    },
+    /// Bytes are of all parts, before decompression
+    DownloadProgress {
+        part: usize,
+        #[serde(flatten)]
+        progress: ByteProgress,
+    },
+    PartChecksumFailed {
+        part: usize,
+    },
+    Mirror {
+        url: &'a str,
+    },
+    Retry {
+        attempt: usize,
+        max_attempts: usize,
+        delay_secs: f64,
+    },
+    /// Bytes that reached the disk, the total is not known
+    WriteProgress {
+        #[serde(flatten)]
+        progress: ByteProgress,
+    },
+    VerifyStart,
+    VerifyProgress {
+        #[serde(flatten)]
+        progress: ByteProgress,
+    },
+    Finished,
+    Failed {
+        /// From [`Error::kind`], or `other` for errors from outside the installer
+        error: &'static str,
+        message: String,
+    },
+}
+
+impl<'a> Event<'a> {
+    pub fn failed(e: &anyhow::Error) -> Self {
+        Event::Failed {
+            error: e.downcast_ref().map(Error::kind).unwrap_or("other"),
+            message: format!("{e:#}"),
+        }
+    }
+
+    pub fn print(&self) {
+        // Only fails on data that is not valid JSON, which these never hold
+        if let Ok(line) = serde_json::to_string(self) {
+            println!("{line}");
+        }
+    }
+}
+
+impl<'a> From<&'a InstallProgress> for Event<'a> {
+    fn from(progress: &'a InstallProgress) -> Self {
+        match progress {
+            InstallProgress::IsoCached => Event::Cached,
+            InstallProgress::IsoDownloadStart(parts) => Event::DownloadStart { parts: *parts },
+            InstallProgress::IsoDownloadProgress(part, progress) => Event::DownloadProgress {
+                part: *part,
+                progress: *progress,
+            },
+            InstallProgress::IsoPartChecksumFailed(part) => {
+                Event::PartChecksumFailed { part: *part }
+            }
+            InstallProgress::IsoMirror(url) => Event::Mirror { url },
+            InstallProgress::IsoRetry(attempt, delay) => Event::Retry {
+                attempt: *attempt,
+                max_attempts: RETRIES,
+                delay_secs: delay.as_secs_f64(),
+            },
+            InstallProgress::IsoWriteProgress(progress) => Event::WriteProgress {
+                progress: *progress,
+            },
+            InstallProgress::VerifyStart => Event::VerifyStart,
+            InstallProgress::VerifyProgress(progress) => Event::VerifyProgress {
+                progress: *progress,
+            },
+            InstallProgress::Finished => Event::Finished,
+            InstallProgress::Failed(e) => Event::Failed {
+                error: e.kind(),
+                message: format!("{e:#}"),
+            },
+        }
+    }
+}
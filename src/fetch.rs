@@ -0,0 +1,194 @@
+use anyhow::{Context, Result, anyhow};
+use bytes::{Buf, Bytes};
+use futures::{StreamExt, stream::BoxStream};
+use reqwest::{
+    Client, StatusCode,
+    header::{CONTENT_RANGE, RANGE},
+};
+use std::time::Duration;
+
+/// Time without any data after which a mirror is given up on
+const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Number of times every mirror is tried again after all of them failed
+pub const RETRIES: usize = 5;
+/// Wait before the first retry, doubled for each one after it
+const BACKOFF: Duration = Duration::from_secs(1);
+
+pub fn client() -> Result<Client> {
+    Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .read_timeout(STALL_TIMEOUT)
+        .build()
+        .context("Failed to create http client")
+}
+
+pub enum Chunk {
+    Data(Bytes),
+    /// Every mirror failed, they are tried again after the delay
+    Retry(usize, Duration),
+}
+
+/// Reads a range of an ISO part, moving on to the next mirror whenever one fails.
+///
+/// Each mirror continues from where the previous one stopped. Once all of them failed,
+/// they are tried again with a growing delay, unless none of the failures could be temporary.
+pub struct PartReader<'a> {
+    client: &'a Client,
+    urls: &'a [String],
+    mirror: usize,
+    /// Offset in the part of the next byte to be read
+    pos: u64,
+    /// End of the range to read, or the end of the part
+    end: Option<u64>,
+    /// Length of the whole part, once known
+    len: Option<u64>,
+    /// Bytes the current mirror sends that have already been read from another one
+    skip: u64,
+    stream: Option<BoxStream<'static, reqwest::Result<Bytes>>>,
+    errors: Vec<String>,
+    /// Whether any mirror failed in a way that could work when tried again
+    transient: bool,
+    /// Retries since data was last read
+    attempt: usize,
+    backoff: Option<Duration>,
+}
+
+impl<'a> PartReader<'a> {
+    pub fn new(client: &'a Client, urls: &'a [String], start: u64, end: Option<u64>) -> Self {
+        Self {
+            client,
+            urls,
+            mirror: 0,
+            pos: start,
+            end,
+            len: None,
+            skip: 0,
+            stream: None,
+            errors: vec![],
+            transient: false,
+            attempt: 0,
+            backoff: None,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.urls[self.mirror.min(self.urls.len() - 1)]
+    }
+
+    pub fn mirror(&self) -> usize {
+        self.mirror
+    }
+
+    pub fn len(&self) -> Option<u64> {
+        self.len
+    }
+
+    /// Gets the next chunk, or `None` once the range has been read.
+    pub async fn next(&mut self) -> Result<Option<Chunk>> {
+        if let Some(delay) = self.backoff.take() {
+            tokio::time::sleep(delay).await;
+        }
+        loop {
+            if let Some(end) = self.end.or(self.len)
+                && self.pos >= end
+            {
+                return Ok(None);
+            }
+            if self.mirror >= self.urls.len() {
+                if !self.transient || self.attempt == RETRIES {
+                    return Err(anyhow!("All mirrors failed: {}", self.errors.join(", ")));
+                }
+                let delay = BACKOFF * 2u32.pow(self.attempt as u32);
+                self.attempt += 1;
+                self.mirror = 0;
+                self.errors.clear();
+                self.transient = false;
+                self.backoff = Some(delay);
+                return Ok(Some(Chunk::Retry(self.attempt, delay)));
+            }
+            let Some(stream) = &mut self.stream else {
+                match self.connect().await {
+                    Ok(true) => {}
+                    Ok(false) => return Ok(None),
+                    Err(e) => self.fail(e),
+                }
+                continue;
+            };
+            match stream.next().await {
+                Some(Ok(mut chunk)) => {
+                    if self.skip > 0 {
+                        let n = self.skip.min(chunk.len() as u64);
+                        self.skip -= n;
+                        chunk.advance(n as usize);
+                    }
+                    if let Some(end) = self.end {
+                        chunk.truncate((end - self.pos).min(chunk.len() as u64) as usize);
+                    }
+                    if !chunk.is_empty() {
+                        self.pos += chunk.len() as u64;
+                        self.attempt = 0;
+                        return Ok(Some(Chunk::Data(chunk)));
+                    }
+                }
+                Some(Err(e)) => self.fail(e.into()),
+                None if self.end.or(self.len).is_none() => return Ok(None),
+                None => self.fail(anyhow!("Download ended early")),
+            }
+        }
+    }
+
+    fn fail(&mut self, e: anyhow::Error) {
+        // The server answered, but refused the request
+        let refused = e
+            .downcast_ref::<reqwest::Error>()
+            .and_then(reqwest::Error::status)
+            .is_some_and(|status| {
+                status.is_client_error()
+                    && status != StatusCode::REQUEST_TIMEOUT
+                    && status != StatusCode::TOO_MANY_REQUESTS
+            });
+        self.transient |= !refused;
+        self.errors.push(format!("{}: {e:#}", self.url()));
+        self.mirror += 1;
+        self.stream = None;
+    }
+
+    /// Starts a request to the current mirror, returns false if there is nothing left to read.
+    async fn connect(&mut self) -> Result<bool> {
+        let url = self.url();
+        let mut request = self.client.get(url);
+        if self.pos > 0 || self.end.is_some() {
+            let end = self.end.map(|e| (e - 1).to_string()).unwrap_or_default();
+            request = request.header(RANGE, format!("bytes={}-{end}", self.pos));
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to request ISO url: {url}"))?;
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE
+            && self.pos > 0
+            && self.end.is_none()
+        {
+            // Already read to the end of the part
+            return Ok(false);
+        }
+        let response = response.error_for_status()?;
+        if response.status() == StatusCode::PARTIAL_CONTENT {
+            self.skip = 0;
+            // Content-Range: bytes <start>-<end>/<len>
+            self.len = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|range| range.to_str().ok())
+                .and_then(|range| range.rsplit_once('/'))
+                .and_then(|(_, len)| len.parse().ok());
+        } else {
+            // Servers that ignore the range send the whole part
+            self.skip = self.pos;
+            self.len = response.content_length();
+        }
+        self.stream = Some(response.bytes_stream().boxed());
+        Ok(true)
+    }
+}
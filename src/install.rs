@@ -1,20 +1,45 @@
-use crate::disk::BlockDevice;
-use crate::distro::Distro;
+use crate::disk::{self, BlockDevice};
+use crate::distro::{Distro, DownloadProgress};
 use crate::error::Error;
-use futures::Stream;
-use iced::{stream::channel, task::Sipper};
+use crate::iso_cache::IsoCache;
+use crate::local_iso::LocalIso;
+use crate::progress::{ByteProgress, RateMeter};
+use crate::resume::ResumeState;
+use crate::verify::{self, Written};
+use crate::wipe;
+use anyhow::Context;
+use futures::{SinkExt, Stream};
+use futures_channel::mpsc::Sender;
+use iced::{
+    stream::channel,
+    task::{Sipper, Straw},
+};
 use std::fmt::Display;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs::File;
 use tokio_util::sync::CancellationToken;
 
 #[derive(Debug)]
 pub enum InstallProgress {
+    /// The ISO is copied from the cache instead of being downloaded
+    IsoCached,
     /// Part
     IsoDownloadStart(usize),
-    /// Part, Progress
-    IsoDownloadProgress(usize, f64),
+    /// Part, Progress of all parts
+    IsoDownloadProgress(usize, ByteProgress),
+    /// Part, which is downloaded again
+    IsoPartChecksumFailed(usize),
+    /// Mirror
+    IsoMirror(String),
+    /// Attempt, Delay before it
+    IsoRetry(usize, Duration),
+    /// Bytes that reached the disk, with the rate the disk takes them at
+    IsoWriteProgress(ByteProgress),
+    /// What was written is being read back from the disk
+    VerifyStart,
+    VerifyProgress(ByteProgress),
     Finished,
     Failed(Error),
 }
@@ -28,10 +53,66 @@ struct Installer {
 
 #[derive(Debug, Clone, Hash)]
 pub struct InstallSettings {
-    distro: Distro,
+    source: IsoSource,
     download_target: DownloadTarget,
 }
 
+/// Where the ISO comes from
+#[derive(Debug, Clone, Hash)]
+pub enum IsoSource {
+    Distro(Distro),
+    Local(LocalIso),
+}
+
+impl Display for IsoSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IsoSource::Distro(distro) => write!(f, "{}", distro.name),
+            IsoSource::Local(iso) => write!(f, "{}", iso.path.display()),
+        }
+    }
+}
+
+impl IsoSource {
+    /// Fails when the ISO is in an archive that can't be extracted
+    pub async fn check_archive(&self) -> anyhow::Result<()> {
+        match self {
+            IsoSource::Distro(distro) => distro.check_archive().await,
+            IsoSource::Local(iso) => iso.check_archive().await,
+        }
+    }
+
+    /// Space the ISO takes on the disk, as far as it is known before writing it
+    pub fn image_size(&self) -> Option<ImageSize> {
+        let (distro, len) = match self {
+            IsoSource::Distro(distro) => (Some(distro), distro.download_size?),
+            IsoSource::Local(iso) => (
+                iso.distro.as_ref(),
+                std::fs::metadata(&iso.path).ok()?.len(),
+            ),
+        };
+        Some(match distro.and_then(|distro| distro.compression()) {
+            Some(_) => ImageSize::AtLeast(len),
+            None => ImageSize::Exact(len),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageSize {
+    Exact(u64),
+    /// Only the compressed size is known, and the ISO is larger
+    AtLeast(u64),
+}
+
+impl ImageSize {
+    pub fn len(self) -> u64 {
+        match self {
+            ImageSize::Exact(len) | ImageSize::AtLeast(len) => len,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Hash)]
 pub enum DownloadTarget {
     BlockDev(BlockDevice),
@@ -42,7 +123,12 @@ impl Display for DownloadTarget {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             DownloadTarget::BlockDev(block_device) => {
-                write!(f, "Block Device {}", block_device.name)
+                write!(
+                    f,
+                    "{} ({})",
+                    block_device.name(),
+                    block_device.os_identifier
+                )
             }
             DownloadTarget::File(path_buf) => write!(f, "{}", path_buf.display()),
         }
@@ -50,12 +136,42 @@ impl Display for DownloadTarget {
 }
 
 impl InstallSettings {
-    pub fn new(distro: Distro, download_target: DownloadTarget) -> Self {
+    pub fn new(source: IsoSource, download_target: DownloadTarget) -> Self {
         Self {
-            distro,
+            source,
             download_target,
         }
     }
+
+    pub fn source(&self) -> &IsoSource {
+        &self.source
+    }
+
+    pub fn download_target(&self) -> &DownloadTarget {
+        &self.download_target
+    }
+
+    /// Refuses disks that the ISO can't, or must not, be written to
+    pub fn check_target(&self) -> Result<(), Error> {
+        let DownloadTarget::BlockDev(device) = &self.download_target else {
+            return Ok(());
+        };
+        if device.system {
+            return Err(Error::SystemDisk(device.name()));
+        }
+        if device.read_only {
+            return Err(Error::ReadOnly(device.name()));
+        }
+        if self
+            .source
+            .image_size()
+            .is_some_and(|size| size.len() > device.size)
+        {
+            return Err(Error::TargetTooSmall(device.name()));
+        }
+        Ok(())
+    }
+
     pub fn install(
         &self,
         file: Arc<File>,
@@ -63,44 +179,143 @@ impl InstallSettings {
     ) -> impl Stream<Item = InstallProgress> + use<> {
         let settings = self.clone();
         let state = Installer { settings, file, ct };
-        channel(
-            10,
-            async move |mut sender: futures_channel::mpsc::Sender<InstallProgress>| {
+        channel(10, async move |mut sender: Sender<InstallProgress>| {
+            if let Err(e) = state.run(&mut sender).await {
+                // Nothing is left to report to once the receiver is gone
+                let _ = sender
+                    .send(InstallProgress::Failed(if state.ct.is_cancelled() {
+                        Error::Cancelled
+                    } else {
+                        match e.downcast::<Error>() {
+                            Ok(e) => e,
+                            Err(e) => Error::IsoDownload(e.context(format!(
+                                "Failed to write {} to {}",
+                                state.settings.source, state.settings.download_target
+                            ))),
+                        }
+                    }))
+                    .await;
+                return;
+            }
+            let _ = sender.send(InstallProgress::Finished).await;
+        })
+    }
+}
+
+impl Installer {
+    async fn run(&self, sender: &mut Sender<InstallProgress>) -> anyhow::Result<()> {
+        self.settings.check_target()?;
+        self.settings.source.check_archive().await?;
+        // The disk was opened a while ago, and could have been swapped since
+        if let DownloadTarget::BlockDev(device) = &self.settings.download_target {
+            disk::check_device(&self.file, device).await?;
+            // Anything of the old layout past the end of the image would still be found
+            let file = self.file.try_clone().await?.into_std().await;
+            tokio::task::spawn_blocking(move || wipe::wipe(&file))
+                .await?
+                .with_context(|| format!("Failed to wipe {}", device.name()))?;
+        }
+        let written = self.write(sender).await?;
+        // Cheap flash drives can lose data without any error
+        if let DownloadTarget::BlockDev(_) = &self.settings.download_target {
+            sender.send(InstallProgress::VerifyStart).await?;
+            let mut rate = RateMeter::new(0);
+            let len = written.len;
+            let mut check = verify::read_back(self.file.clone(), written, self.ct.clone()).pin();
+            while let Some(done) = check.sip().await {
                 sender
-                    .try_send(InstallProgress::IsoDownloadStart(
-                        state.settings.distro.iso.len(),
+                    .send(InstallProgress::VerifyProgress(
+                        rate.progress(done, Some(len)),
                     ))
-                    .unwrap();
-                let mut download = state
-                    .settings
-                    .distro
-                    .download_iso(state.file, state.ct.clone())
-                    .pin();
-                while let Some((part, progress)) = download.sip().await {
-                    sender
-                        .try_send(InstallProgress::IsoDownloadProgress(part, progress))
-                        .unwrap();
+                    .await?;
+            }
+            check.await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the ISO to the target, returning what was written
+    async fn write(&self, sender: &mut Sender<InstallProgress>) -> anyhow::Result<Written> {
+        let distro = match &self.settings.source {
+            IsoSource::Distro(distro) => distro,
+            IsoSource::Local(iso) => {
+                return forward(iso.flash(self.file.clone(), self.ct.clone()), sender).await;
+            }
+        };
+        let resume = match &self.settings.download_target {
+            DownloadTarget::File(path) => Some(ResumeState::path_for(path)),
+            DownloadTarget::BlockDev(_) => None,
+        };
+        let cache = IsoCache::open();
+        if let Some(cache) = &cache
+            && let Some(sum) = distro.sha256()
+            && let Some(cached) = cache.get(sum)
+        {
+            sender.send(InstallProgress::IsoCached).await?;
+            let cached = LocalIso {
+                path: cached,
+                distro: Some(distro.clone()),
+            };
+            match forward(cached.flash(self.file.clone(), self.ct.clone()), sender).await {
+                Err(e) if matches!(e.downcast_ref(), Some(Error::LocalChecksum)) => {
+                    cache.remove(sum).await;
                 }
-                match download.await {
-                    Ok(iso) => iso,
-                    Err(e) => {
-                        sender
-                            .try_send(InstallProgress::Failed(
-                                if state.ct.clone().is_cancelled() {
-                                    Error::Cancelled
-                                } else {
-                                    Error::IsoDownload(e.context(format!(
-                                        "Failed to download ISO to {}",
-                                        state.settings.download_target
-                                    )))
-                                },
-                            ))
-                            .unwrap();
-                        return;
+                Ok(written) => {
+                    // Whatever was downloaded before has been overwritten
+                    if let Some(resume) = resume {
+                        let _ = tokio::fs::remove_file(resume).await;
                     }
-                };
-                sender.try_send(InstallProgress::Finished).unwrap();
-            },
-        )
+                    return Ok(written);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        sender
+            .send(InstallProgress::IsoDownloadStart(distro.iso.len()))
+            .await?;
+        // A file is kept anyway, but a block device is likely to be flashed again
+        let entry = match (&cache, distro.sha256(), &self.settings.download_target) {
+            (Some(cache), Some(sum), DownloadTarget::BlockDev(_)) => cache.create(sum).await.ok(),
+            _ => None,
+        };
+        let cache_file = entry.as_ref().map(|e| e.file.try_clone()).transpose()?;
+        let download = distro.download_iso(self.file.clone(), resume, cache_file, self.ct.clone());
+        let written = forward(download, sender).await?;
+        if let (Some(cache), Some(entry)) = (&cache, entry) {
+            // Failing to cache the ISO only matters the next time it is flashed
+            let _ = cache.insert(entry).await;
+        }
+        Ok(written)
+    }
+}
+
+/// Passes on the progress of `straw`, until it finishes
+async fn forward<T>(
+    straw: impl Straw<T, DownloadProgress, anyhow::Error>,
+    sender: &mut Sender<InstallProgress>,
+) -> anyhow::Result<T> {
+    let mut straw = straw.pin();
+    let mut rate: Option<RateMeter> = None;
+    let mut write_rate = RateMeter::new(0);
+    while let Some(progress) = straw.sip().await {
+        sender
+            .send(match progress {
+                DownloadProgress::Progress(part, done, total) => {
+                    let rate = rate.get_or_insert_with(|| RateMeter::new(done));
+                    InstallProgress::IsoDownloadProgress(part, rate.progress(done, total))
+                }
+                DownloadProgress::PartChecksumFailed(part) => {
+                    InstallProgress::IsoPartChecksumFailed(part)
+                }
+                DownloadProgress::Mirror(_, url) => InstallProgress::IsoMirror(url),
+                DownloadProgress::Retry(_, attempt, delay) => {
+                    InstallProgress::IsoRetry(attempt, delay)
+                }
+                DownloadProgress::Written(written) => {
+                    InstallProgress::IsoWriteProgress(write_rate.progress(written, None))
+                }
+            })
+            .await?;
     }
+    straw.await
 }
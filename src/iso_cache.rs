@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Largest total size of the cached downloads, in bytes, 0 turns the cache off
+pub const LIMIT_VAR: &str = "T2LINUX_ISO_CACHE_LIMIT";
+const DEFAULT_LIMIT: u64 = 16_000_000_000;
+/// Suffix of downloads that are still being written
+const PARTIAL: &str = "partial";
+
+/// Downloads kept to flash them again, named after the checksum in the catalog.
+///
+/// The least recently used ones are removed once the cache is over its limit.
+#[derive(Debug, Clone)]
+pub struct IsoCache {
+    dir: PathBuf,
+    limit: u64,
+}
+
+/// A download being written into the cache
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub file: File,
+    partial: PathBuf,
+    path: PathBuf,
+}
+
+impl IsoCache {
+    /// Gets the cache, unless it is turned off
+    pub fn open() -> Option<IsoCache> {
+        let limit = match std::env::var(LIMIT_VAR) {
+            Ok(limit) => limit.parse().ok()?,
+            Err(_) => DEFAULT_LIMIT,
+        };
+        if limit == 0 {
+            return None;
+        }
+        Some(IsoCache {
+            dir: dirs::cache_dir()?.join("t2linux-installer").join("isos"),
+            limit,
+        })
+    }
+
+    /// Only checksums are used as names, so the catalog can't point anywhere else
+    fn path(&self, sha256: &str) -> Option<PathBuf> {
+        let key = sha256.to_ascii_lowercase();
+        (key.len() == 64 && key.bytes().all(|b| b.is_ascii_hexdigit())).then(|| self.dir.join(key))
+    }
+
+    /// Finds the cached download with `sha256`, it still has to be checked against it.
+    pub fn get(&self, sha256: &str) -> Option<PathBuf> {
+        let path = self.path(sha256)?;
+        let file = File::options().append(true).open(&path).ok()?;
+        // Marks it as used, for eviction
+        file.set_modified(SystemTime::now()).ok()?;
+        Some(path)
+    }
+
+    pub async fn create(&self, sha256: &str) -> Result<CacheEntry> {
+        let path = self.path(sha256).context("Invalid checksum")?;
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let partial = path.with_extension(PARTIAL);
+        let file = File::create(&partial).context("Failed to create cached ISO")?;
+        Ok(CacheEntry {
+            file,
+            partial,
+            path,
+        })
+    }
+
+    /// Adds a download once it has been checked, making room for it
+    pub async fn insert(&self, entry: CacheEntry) -> Result<()> {
+        tokio::fs::rename(&entry.partial, &entry.path).await?;
+        self.evict().await
+    }
+
+    pub async fn remove(&self, sha256: &str) {
+        if let Some(path) = self.path(sha256) {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+
+    async fn evict(&self) -> Result<()> {
+        let mut entries = vec![];
+        let mut dir = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() && !is_partial(&entry.path()) {
+                entries.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        entries.sort();
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        for (_, len, path) in entries {
+            if total <= self.limit {
+                break;
+            }
+            tokio::fs::remove_file(path).await?;
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CacheEntry {
+    /// Removes the download if it was never finished
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.partial);
+    }
+}
+
+fn is_partial(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == PARTIAL)
+}
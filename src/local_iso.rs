@@ -0,0 +1,157 @@
+use crate::decompress::{self, ZIP_HEADER_LEN};
+use crate::distro::{
+    CompressionAlgorithim, Distro, DownloadProgress, IsoWriter, open_target, sha256_matches,
+};
+use crate::error::Error;
+use crate::resume;
+use crate::verify::Written;
+use anyhow::{Context, Result, anyhow};
+use bytes::BytesMut;
+use iced::task::{Straw, sipper};
+use sha2::{Digest, Sha256};
+use sipper::Sender;
+use std::{
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{fs::File, io::AsyncReadExt};
+use tokio_util::sync::CancellationToken;
+
+/// Amount of the file read at once
+const READ_LEN: usize = 1 << 20;
+
+/// An ISO or IMG on disk, instead of one from the catalog
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocalIso {
+    pub path: PathBuf,
+    /// The distro in the catalog that this is a download of
+    pub distro: Option<Distro>,
+}
+
+impl LocalIso {
+    /// Looks for the distro in the catalog with the same checksum as the file
+    pub async fn identify(path: PathBuf, distros: &[Distro]) -> Result<LocalIso> {
+        let mut distro = None;
+        if distros.iter().any(|d| d.sha256().is_some()) {
+            let file = std::fs::File::open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            let len = file.metadata()?.len();
+            let hasher = resume::hash_range(Arc::new(file), 0, len, Sha256::new()).await?;
+            let sum = hex::encode(hasher.finalize());
+            distro = distros
+                .iter()
+                .find(|d| d.sha256().is_some_and(|s| s.eq_ignore_ascii_case(&sum)))
+                .cloned();
+        }
+        Ok(LocalIso { path, distro })
+    }
+
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or(self.path.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Fails when the file is an archive that can't be extracted, before anything is erased
+    pub async fn check_archive(&self) -> Result<()> {
+        if self.distro.as_ref().and_then(Distro::compression) != Some(&CompressionAlgorithim::Zip) {
+            return Ok(());
+        }
+        let mut header = vec![0; ZIP_HEADER_LEN];
+        let mut file = File::open(&self.path)
+            .await
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        let len = file.read(&mut header).await?;
+        decompress::check_zip(&header[..len])?;
+        Ok(())
+    }
+
+    /// Writes the file into `file`.
+    ///
+    /// When it is a download of a distro in the catalog, it is decompressed and checked
+    /// like one, and `file` has to be written again when this fails with
+    /// [`Error::LocalChecksum`].
+    pub fn flash(
+        &self,
+        file: Arc<File>,
+        ct: CancellationToken,
+    ) -> impl Straw<Written, DownloadProgress, anyhow::Error> {
+        let iso = self.clone();
+        sipper(async move |mut sender| {
+            let Some(distro) = &iso.distro else {
+                return copy(&iso.path, None, None, file, &ct, &mut sender).await;
+            };
+            distro.check_signature()?;
+            let sum = distro.sha256().context("Local ISO has no checksum")?;
+            copy(
+                &iso.path,
+                distro.compression(),
+                Some(sum),
+                file,
+                &ct,
+                &mut sender,
+            )
+            .await
+        })
+    }
+}
+
+/// Writes `path` into `file`, decompressing it and checking it against `sha256` on the way.
+///
+/// `path` is read to the end even if writing fails, so that it is always checked.
+/// Returns what was written.
+async fn copy(
+    path: &Path,
+    compression: Option<&CompressionAlgorithim>,
+    sha256: Option<&str>,
+    file: Arc<File>,
+    ct: &CancellationToken,
+    sender: &mut Sender<DownloadProgress>,
+) -> Result<Written> {
+    let iso_file = open_target(&file).await?;
+    let mut source = File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let metadata = source.metadata().await?;
+    let target = iso_file.metadata()?;
+    if target.is_file() {
+        if (target.dev(), target.ino()) == (metadata.dev(), metadata.ino()) {
+            return Err(anyhow!("Can't write {} onto itself", path.display()));
+        }
+        iso_file.set_len(0)?;
+    }
+    let total = metadata.len();
+    let mut writer = IsoWriter::new(iso_file, compression.cloned(), None)?;
+    let mut done = 0;
+    loop {
+        let mut buf = BytesMut::with_capacity(READ_LEN);
+        let len = tokio::select! {
+            len = source.read_buf(&mut buf) => len?,
+            _ = ct.cancelled() => return Err(anyhow!("Copy cancelled")),
+        };
+        if len == 0 {
+            break;
+        }
+        done += len as u64;
+        // The rest is still read and checked when the writer failed
+        tokio::select! {
+            _ = writer.push(buf.freeze()) => {}
+            _ = ct.cancelled() => return Err(anyhow!("Copy cancelled")),
+        }
+        sender
+            .send(DownloadProgress::Progress(1, done, Some(total)))
+            .await;
+        writer.report(sender).await;
+    }
+    let (hasher, written) = writer.finish(sender).await?;
+    // A corrupt file can make decompressing it fail, the mismatch explains that better
+    if let Some(sum) = sha256
+        && !sha256_matches(sum, hasher)?
+    {
+        return Err(Error::LocalChecksum.into());
+    }
+    written
+}
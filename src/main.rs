@@ -1,17 +1,38 @@
+use crate::cli::Cli;
 use crate::ui::app::App;
+use clap::Parser;
 
 mod ui {
     pub mod app;
     pub mod download_page;
     pub mod finish_page;
+    pub mod format;
     pub mod main_page;
 }
+mod catalog;
+mod cli;
+mod decompress;
+mod device_writer;
 pub mod disk;
 mod distro;
 mod error;
+mod event;
+mod fetch;
 mod install;
+mod iso_cache;
+mod local_iso;
+mod pipeline;
+mod progress;
+mod resume;
+mod segmented;
+mod signature;
+mod verify;
+mod wipe;
 
 fn main() -> iced::Result {
+    if let Some(command) = Cli::parse().command {
+        std::process::exit(cli::run(command));
+    }
     iced::application(App::new, App::update, App::view)
         .title(App::title)
         .subscription(App::subscription)
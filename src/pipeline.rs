@@ -0,0 +1,198 @@
+use anyhow::{Context, Result};
+use bytes::{Buf, Bytes};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{self, Read},
+    sync::Arc,
+};
+use tokio::{
+    sync::{
+        OwnedSemaphorePermit, Semaphore,
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::JoinHandle,
+};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+/// Most downloaded data held in memory between the network and the other stages
+const BUFFER_LEN: usize = 32 << 20;
+
+/// Downloaded data, which takes up room in the buffer until every stage is done with it
+#[derive(Clone)]
+struct Piece {
+    data: Bytes,
+    _room: Arc<OwnedSemaphorePermit>,
+}
+
+enum HashJob {
+    Data(Piece),
+    /// Gets the hash of the current part, and starts the next one
+    EndPart(oneshot::Sender<Sha256>),
+}
+
+/// Hands downloaded data to a thread that hashes it, and one that writes it, so the
+/// network and the disk don't wait on each other.
+///
+/// Pushing waits once the buffer is full. Dropping it before it is finished stops the
+/// writer.
+pub struct Pipeline<T> {
+    /// Dropped first, so the writer never mistakes the channel closing for the end
+    abort: DropGuard,
+    room: Arc<Semaphore>,
+    hash_tx: UnboundedSender<HashJob>,
+    write_tx: UnboundedSender<Piece>,
+    hasher: JoinHandle<Sha256>,
+    writer: JoinHandle<io::Result<T>>,
+}
+
+impl<T: Send + 'static> Pipeline<T> {
+    /// `write` gets the data through the reader it is given, on its own thread. Continues
+    /// after data that is already in `hasher`, and in `part_hasher` for the current part
+    pub fn resume(
+        mut hasher: Sha256,
+        mut part_hasher: Sha256,
+        write: impl FnOnce(ChannelReader) -> io::Result<T> + Send + 'static,
+    ) -> Self {
+        let (hash_tx, mut hash_rx) = mpsc::unbounded_channel();
+        let hasher = tokio::task::spawn_blocking(move || {
+            while let Some(job) = hash_rx.blocking_recv() {
+                match job {
+                    HashJob::Data(piece) => {
+                        hasher.update(&piece.data);
+                        part_hasher.update(&piece.data);
+                    }
+                    HashJob::EndPart(tx) => {
+                        let _ = tx.send(std::mem::take(&mut part_hasher));
+                    }
+                }
+            }
+            hasher
+        });
+        let abort = CancellationToken::new();
+        let (write_tx, rx) = mpsc::unbounded_channel();
+        let reader = ChannelReader {
+            rx,
+            piece: None,
+            abort: abort.clone(),
+        };
+        Self {
+            abort: abort.drop_guard(),
+            room: Arc::new(Semaphore::new(BUFFER_LEN)),
+            hash_tx,
+            write_tx,
+            hasher,
+            writer: tokio::task::spawn_blocking(move || write(reader)),
+        }
+    }
+
+    /// Waits for room in the buffer, and hands `data` to the other stages.
+    ///
+    /// Returns false once the writer is gone, which only happens when it failed.
+    pub async fn push(&mut self, data: Bytes) -> bool {
+        // A chunk larger than the whole buffer only waits for it to be empty
+        let len = data.len().min(BUFFER_LEN) as u32;
+        let room = self
+            .room
+            .clone()
+            .acquire_many_owned(len)
+            .await
+            .expect("buffer is never closed");
+        let piece = Piece {
+            data,
+            _room: Arc::new(room),
+        };
+        let _ = self.hash_tx.send(HashJob::Data(piece.clone()));
+        self.write_tx.send(piece).is_ok()
+    }
+
+    /// Hash of the data pushed since the last call
+    pub async fn end_part(&mut self) -> Result<Sha256> {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.hash_tx.send(HashJob::EndPart(tx));
+        rx.await.context("Hashing the download stopped")
+    }
+
+    /// Waits for everything to be hashed, returning the hash and the writer, which can
+    /// still be writing.
+    pub async fn finish(self) -> Result<(Sha256, JoinHandle<io::Result<T>>)> {
+        let Pipeline {
+            abort,
+            hash_tx,
+            write_tx,
+            hasher,
+            writer,
+            ..
+        } = self;
+        drop((hash_tx, write_tx));
+        let hasher = hasher.await?;
+        abort.disarm();
+        Ok((hasher, writer))
+    }
+}
+
+/// Blocking reader over the data pushed into a [`Pipeline`].
+///
+/// Fails once the pipeline is dropped without being finished, so nothing more is written.
+pub struct ChannelReader {
+    rx: UnboundedReceiver<Piece>,
+    piece: Option<Piece>,
+    abort: CancellationToken,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(piece) = &mut self.piece
+                && piece.data.has_remaining()
+            {
+                let len = buf.len().min(piece.data.remaining());
+                piece.data.copy_to_slice(&mut buf[..len]);
+                return Ok(len);
+            }
+            if self.abort.is_cancelled() {
+                return Err(io::Error::other("Download stopped"));
+            }
+            // Keeps its room in the buffer until it has been read
+            self.piece = self.rx.blocking_recv();
+            if self.piece.is_none() && !self.abort.is_cancelled() {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn hashes_parts_and_writes_everything() {
+        let mut pipeline = Pipeline::resume(Sha256::new(), Sha256::new(), |mut reader| {
+            let mut written = vec![];
+            reader.read_to_end(&mut written)?;
+            Ok(written)
+        });
+        assert!(pipeline.push(Bytes::from_static(b"first ")).await);
+        assert!(pipeline.push(Bytes::from_static(b"part")).await);
+        let first = pipeline.end_part().await.unwrap();
+        assert!(pipeline.push(Bytes::from_static(b", second")).await);
+        let second = pipeline.end_part().await.unwrap();
+        let (hasher, writer) = pipeline.finish().await.unwrap();
+        assert_eq!(first.finalize(), Sha256::digest(b"first part"));
+        assert_eq!(second.finalize(), Sha256::digest(b", second"));
+        assert_eq!(hasher.finalize(), Sha256::digest(b"first part, second"));
+        assert_eq!(writer.await.unwrap().unwrap(), b"first part, second");
+    }
+
+    #[tokio::test]
+    async fn dropping_it_stops_the_writer() {
+        let mut pipeline = Pipeline::resume(Sha256::new(), Sha256::new(), |mut reader| {
+            reader.read_to_end(&mut vec![])
+        });
+        assert!(pipeline.push(Bytes::from_static(b"cut off")).await);
+        let Pipeline { abort, writer, .. } = pipeline;
+        drop(abort);
+        assert!(writer.await.unwrap().is_err());
+    }
+}
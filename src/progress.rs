@@ -0,0 +1,102 @@
+use humansize::{DECIMAL, format_size};
+use serde::Serialize;
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+/// How often the rate is sampled
+const SAMPLE_PERIOD: Duration = Duration::from_millis(500);
+/// Weight of the newest sample in the smoothed rate
+const SMOOTHING: f64 = 0.3;
+
+/// Progress of a transfer, over all of its parts
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ByteProgress {
+    pub done: u64,
+    /// Unknown when neither the servers nor the catalog give the size
+    pub total: Option<u64>,
+    /// Bytes per second
+    pub rate: f64,
+}
+
+impl ByteProgress {
+    pub fn fraction(&self) -> Option<f64> {
+        let total = self.total.filter(|total| *total > 0)?;
+        Some((self.done as f64 / total as f64).min(1.0))
+    }
+
+    /// Time left at the current rate
+    pub fn eta(&self) -> Option<Duration> {
+        let total = self.total?;
+        if self.rate < 1.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            total.saturating_sub(self.done) as f64 / self.rate,
+        ))
+    }
+}
+
+impl Display for ByteProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let done = format_size(self.done, DECIMAL);
+        match self.total {
+            Some(total) => write!(f, "{done} of {}", format_size(total, DECIMAL))?,
+            None => write!(f, "{done} downloaded, total size unknown")?,
+        }
+        if self.rate > 0.0 {
+            write!(f, " · {}/s", format_size(self.rate as u64, DECIMAL))?;
+        }
+        if let Some(eta) = self.eta() {
+            write!(f, " · {} left", format_duration(eta))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Smooths the transfer rate, so it does not jump around with every chunk
+#[derive(Debug)]
+pub struct RateMeter {
+    sampled_at: Instant,
+    sampled_done: u64,
+    rate: Option<f64>,
+}
+
+impl RateMeter {
+    pub fn new(done: u64) -> Self {
+        Self {
+            sampled_at: Instant::now(),
+            sampled_done: done,
+            rate: None,
+        }
+    }
+
+    pub fn progress(&mut self, done: u64, total: Option<u64>) -> ByteProgress {
+        let elapsed = self.sampled_at.elapsed();
+        if elapsed >= SAMPLE_PERIOD {
+            // Data that is thrown away to be downloaded again does not count
+            let rate = done.saturating_sub(self.sampled_done) as f64 / elapsed.as_secs_f64();
+            self.rate = Some(match self.rate {
+                Some(smoothed) => smoothed + SMOOTHING * (rate - smoothed),
+                None => rate,
+            });
+            self.sampled_at = Instant::now();
+            self.sampled_done = done;
+        }
+        ByteProgress {
+            done,
+            total,
+            rate: self.rate.unwrap_or(0.0),
+        }
+    }
+}
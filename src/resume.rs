@@ -0,0 +1,190 @@
+use crate::distro::IsoPart;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    fs::File,
+    io,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Progress of a download to a file, kept next to the file so it can be continued later.
+///
+/// Only completed parts are recorded, how far into the current part the download got is
+/// taken from the length of the file itself. A download to a disk only keeps it in memory,
+/// to start a corrupt part over.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResumeState {
+    /// Where it is saved, unless it is only kept in memory
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    iso: Vec<IsoPart>,
+    /// Lengths of the parts that have been completely downloaded
+    part_lens: Vec<u64>,
+    /// Set for segmented downloads, which do not fill the file in order
+    #[serde(default)]
+    segments: Option<SegmentProgress>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SegmentProgress {
+    /// Lengths of every part, the segments are only valid for the same lengths
+    pub part_lens: Vec<u64>,
+    pub done: BTreeSet<usize>,
+}
+
+impl ResumeState {
+    pub fn path_for(target: &Path) -> PathBuf {
+        let mut path = OsString::from(target.as_os_str());
+        path.push(".t2linux-resume");
+        PathBuf::from(path)
+    }
+
+    pub fn new(path: PathBuf, iso: &[IsoPart]) -> Self {
+        Self {
+            path: Some(path),
+            ..Self::in_memory(iso)
+        }
+    }
+
+    /// Progress that is never saved
+    pub fn in_memory(iso: &[IsoPart]) -> Self {
+        Self {
+            path: None,
+            iso: iso.to_vec(),
+            part_lens: vec![],
+            segments: None,
+        }
+    }
+
+    /// Loads the saved state at `path`, if there is one for the same ISO.
+    pub async fn load(path: PathBuf, iso: &[IsoPart]) -> Option<Self> {
+        let data = tokio::fs::read(&path).await.ok()?;
+        let state: ResumeState = serde_json::from_slice(&data).ok()?;
+        (state.iso == iso).then_some(Self {
+            path: Some(path),
+            ..state
+        })
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        tokio::fs::write(path, serde_json::to_vec(self)?)
+            .await
+            .context("Failed to save download resume state")
+    }
+
+    pub async fn remove(self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).context("Failed to remove download resume state")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_segmented(&self) -> bool {
+        self.segments.is_some()
+    }
+
+    /// Gets the segments completed so far, starting over if the parts have changed.
+    pub fn segments(&mut self, part_lens: &[u64]) -> &mut SegmentProgress {
+        let segments = self.segments.get_or_insert_default();
+        if segments.part_lens != part_lens {
+            *segments = SegmentProgress {
+                part_lens: part_lens.to_vec(),
+                done: BTreeSet::new(),
+            };
+        }
+        segments
+    }
+
+    /// Length of the parts that have been completely downloaded
+    pub fn written(&self) -> u64 {
+        self.part_lens.iter().sum()
+    }
+
+    pub fn finish_part(&mut self, len: u64) {
+        self.part_lens.push(len);
+    }
+
+    /// Finds the part and offset into it to continue from, given how much is already written.
+    pub fn position(&mut self, mut written: u64) -> (usize, u64) {
+        for (part, len) in self.part_lens.iter().enumerate() {
+            if written < *len {
+                self.part_lens.truncate(part);
+                return (part, written);
+            }
+            written -= len;
+        }
+        (self.part_lens.len(), written)
+    }
+}
+
+/// Feeds `len` bytes of `file` starting at `start` into `hasher`.
+pub async fn hash_range(
+    file: Arc<File>,
+    start: u64,
+    len: u64,
+    mut hasher: Sha256,
+) -> Result<Sha256> {
+    tokio::task::spawn_blocking(move || {
+        let mut buf = vec![0; 1 << 20];
+        let end = start + len;
+        let mut pos = start;
+        while pos < end {
+            let n = (end - pos).min(buf.len() as u64) as usize;
+            file.read_exact_at(&mut buf[..n], pos)?;
+            hasher.update(&buf[..n]);
+            pos += n as u64;
+        }
+        Ok::<_, io::Error>(hasher)
+    })
+    .await?
+    .context("Failed to read back downloaded ISO")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(part_lens: &[u64]) -> ResumeState {
+        let mut state = ResumeState::in_memory(&[]);
+        for len in part_lens {
+            state.finish_part(*len);
+        }
+        state
+    }
+
+    #[test]
+    fn position_inside_a_finished_part_starts_it_over() {
+        let mut state = state(&[10, 20]);
+        assert_eq!(state.position(15), (1, 5));
+        assert_eq!(state.written(), 10);
+    }
+
+    #[test]
+    fn position_after_finished_parts() {
+        let mut state = state(&[10, 20]);
+        assert_eq!(state.position(30), (2, 0));
+        assert_eq!(state.written(), 30);
+        assert_eq!(state.position(37), (2, 7));
+    }
+
+    #[test]
+    fn position_at_the_start() {
+        assert_eq!(state(&[]).position(0), (0, 0));
+        let mut state = state(&[10]);
+        assert_eq!(state.position(0), (0, 0));
+        assert_eq!(state.written(), 0);
+    }
+}
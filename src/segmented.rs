@@ -0,0 +1,322 @@
+use crate::distro::{DownloadProgress, IsoPart, PART_RETRIES, sha256_matches};
+use crate::error::Error;
+use crate::fetch::{Chunk, PartReader};
+use crate::resume::{self, ResumeState};
+use anyhow::{Context, Result, anyhow};
+use futures::{FutureExt, StreamExt, stream::FuturesUnordered};
+use reqwest::{
+    Client,
+    header::{ACCEPT_RANGES, CONTENT_LENGTH},
+};
+use sha2::{Digest, Sha256};
+use sipper::Sender;
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fs::File,
+    os::unix::fs::FileExt,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Largest range fetched with a single request
+const SEGMENT_LEN: u64 = 32 << 20;
+/// Number of segments fetched at once
+const CONNECTIONS: usize = 4;
+/// Amount of data buffered before it is written out
+const WRITE_LEN: usize = 1 << 20;
+
+enum SegmentEvent {
+    /// Part, Length
+    Data(usize, u64),
+    /// Part, Mirror
+    Mirror(usize, String),
+    /// Part, Attempt, Delay before it
+    Retry(usize, usize, Duration),
+}
+
+/// Where the segments are written
+pub struct Output {
+    pub file: File,
+    /// Also gets all of the data, when set
+    pub cache: Option<File>,
+}
+
+#[derive(Debug)]
+struct Segment {
+    part: usize,
+    /// Range within the part
+    start: u64,
+    end: u64,
+    /// Where the segment starts in the target
+    offset: u64,
+}
+
+/// Lengths of the parts of an ISO, found before downloading it
+pub struct Probe {
+    pub part_lens: Vec<u64>,
+    /// Whether every part can be fetched in ranges
+    pub ranges: bool,
+}
+
+impl Probe {
+    pub fn len(&self) -> u64 {
+        self.part_lens.iter().sum()
+    }
+}
+
+/// Finds the length of every part, if all of them are known.
+pub async fn probe(client: &Client, iso: &[IsoPart]) -> Option<Probe> {
+    let mut part_lens = vec![];
+    let mut ranges = true;
+    for part in iso {
+        let mut found = None;
+        for url in &part.urls {
+            found = probe_url(client, url).await;
+            // A mirror with ranges is worth looking for, one without is kept in case there is none
+            if found.is_some_and(|(_, part_ranges)| part_ranges) {
+                break;
+            }
+        }
+        let (len, part_ranges) = found?;
+        part_lens.push(len);
+        ranges &= part_ranges;
+    }
+    Some(Probe { part_lens, ranges })
+}
+
+/// Length of `url`, and whether it can be fetched in ranges
+async fn probe_url(client: &Client, url: &str) -> Option<(u64, bool)> {
+    let response = client
+        .head(url)
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?;
+    let headers = response.headers();
+    let ranges = headers
+        .get(ACCEPT_RANGES)
+        .is_some_and(|value| value.to_str().is_ok_and(|value| value == "bytes"));
+    // `Response::content_length` is the length of the (empty) body of a HEAD response
+    let len = headers.get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok()?;
+    Some((len, ranges))
+}
+
+fn plan(part_lens: &[u64]) -> Vec<Segment> {
+    let mut segments = vec![];
+    let mut offset = 0;
+    for (part, len) in part_lens.iter().enumerate() {
+        for start in (0..*len).step_by(SEGMENT_LEN as usize) {
+            let end = (start + SEGMENT_LEN).min(*len);
+            segments.push(Segment {
+                part,
+                start,
+                end,
+                offset: offset + start,
+            });
+        }
+        offset += len;
+    }
+    segments
+}
+
+/// Downloads every part at once into a regular file, split into segments.
+///
+/// The data arrives out of order, so it has to be read back from the file to be checked.
+pub async fn download(
+    client: &Client,
+    iso: &[IsoPart],
+    part_lens: &[u64],
+    output: Output,
+    resume_state: Option<&mut ResumeState>,
+    ct: &CancellationToken,
+    sender: &mut Sender<DownloadProgress>,
+) -> Result<File> {
+    let file = Arc::new(output.file);
+    let cache = output.cache.map(Arc::new);
+    let mut resume_state = resume_state;
+    let segments = plan(part_lens);
+    let mut done = match &mut resume_state {
+        Some(state) => state.segments(part_lens).done.clone(),
+        None => BTreeSet::new(),
+    };
+    let mut part_done = vec![0; part_lens.len()];
+    for i in &done {
+        part_done[segments[*i].part] += segments[*i].end - segments[*i].start;
+    }
+    let mut queue: VecDeque<usize> = (0..segments.len()).filter(|i| !done.contains(i)).collect();
+    let mut retries = 0;
+    let mut mirrors = vec![None; part_lens.len()];
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let mut fetches = FuturesUnordered::new();
+    loop {
+        while fetches.len() < CONNECTIONS
+            && let Some(i) = queue.pop_front()
+        {
+            fetches.push(
+                fetch_segment(
+                    client,
+                    iso,
+                    &segments[i],
+                    file.clone(),
+                    cache.clone(),
+                    event_tx.clone(),
+                )
+                .map(move |res| res.map(|_| i)),
+            );
+        }
+        tokio::select! {
+            Some(event) = event_rx.recv() => match event {
+                SegmentEvent::Data(part, len) => {
+                    part_done[part] += len;
+                    sender
+                        .send(DownloadProgress::Progress(
+                            part + 1,
+                            part_done.iter().sum(),
+                            Some(part_lens.iter().sum()),
+                        ))
+                        .await;
+                }
+                SegmentEvent::Retry(part, attempt, delay) => {
+                    sender.send(DownloadProgress::Retry(part + 1, attempt, delay)).await;
+                }
+                SegmentEvent::Mirror(part, url) => {
+                    if mirrors[part].as_ref() != Some(&url) {
+                        mirrors[part] = Some(url.clone());
+                        sender.send(DownloadProgress::Mirror(part + 1, url)).await;
+                    }
+                }
+            },
+            res = fetches.next() => match res {
+                Some(Ok(i)) => {
+                    done.insert(i);
+                    let part = segments[i].part;
+                    let part_segments = || (0..segments.len()).filter(|j| segments[*j].part == part);
+                    if let Some(sum) = &iso[part].sha256
+                        && part_segments().all(|j| done.contains(&j))
+                    {
+                        let start = segments[i].offset - segments[i].start;
+                        let hasher =
+                            resume::hash_range(file.clone(), start, part_lens[part], Sha256::new())
+                                .await?;
+                        if !sha256_matches(sum, hasher)? {
+                            if retries == PART_RETRIES {
+                                return Err(Error::PartChecksum(part + 1).into());
+                            }
+                            retries += 1;
+                            sender.send(DownloadProgress::PartChecksumFailed(part + 1)).await;
+                            for j in part_segments() {
+                                done.remove(&j);
+                                queue.push_back(j);
+                            }
+                            part_done[part] = 0;
+                        }
+                    }
+                    if let Some(state) = &mut resume_state {
+                        state.segments(part_lens).done.clone_from(&done);
+                        state.save().await?;
+                    }
+                }
+                Some(Err(e)) => return Err(e),
+                None => break,
+            },
+            _ = ct.cancelled() => return Err(anyhow!("Download cancelled")),
+        }
+    }
+    drop(fetches);
+    Arc::try_unwrap(file).map_err(|_| anyhow!("Segment writer still running"))
+}
+
+async fn fetch_segment(
+    client: &Client,
+    iso: &[IsoPart],
+    segment: &Segment,
+    file: Arc<File>,
+    cache: Option<Arc<File>>,
+    events: mpsc::UnboundedSender<SegmentEvent>,
+) -> Result<()> {
+    let mut reader = PartReader::new(
+        client,
+        &iso[segment.part].urls,
+        segment.start,
+        Some(segment.end),
+    );
+    let mut mirror = None;
+    let mut offset = segment.offset;
+    let mut buf = Vec::with_capacity(WRITE_LEN);
+    while let Some(chunk) = reader
+        .next()
+        .await
+        .with_context(|| format!("Failed to download part {}", segment.part + 1))?
+    {
+        let chunk = match chunk {
+            Chunk::Data(chunk) => chunk,
+            Chunk::Retry(attempt, delay) => {
+                let _ = events.send(SegmentEvent::Retry(segment.part, attempt, delay));
+                continue;
+            }
+        };
+        if mirror != Some(reader.mirror()) {
+            mirror = Some(reader.mirror());
+            let _ = events.send(SegmentEvent::Mirror(segment.part, reader.url().to_owned()));
+        }
+        buf.extend_from_slice(&chunk);
+        let _ = events.send(SegmentEvent::Data(segment.part, chunk.len() as u64));
+        if buf.len() >= WRITE_LEN {
+            let buf = std::mem::replace(&mut buf, Vec::with_capacity(WRITE_LEN));
+            offset += write_at(&file, cache.as_ref(), buf, offset).await?;
+        }
+    }
+    write_at(&file, cache.as_ref(), buf, offset).await?;
+    Ok(())
+}
+
+async fn write_at(
+    file: &Arc<File>,
+    cache: Option<&Arc<File>>,
+    buf: Vec<u8>,
+    offset: u64,
+) -> Result<u64> {
+    let file = file.clone();
+    let cache = cache.cloned();
+    tokio::task::spawn_blocking(move || {
+        file.write_all_at(&buf, offset)
+            .context("Failed to write to iso download location")?;
+        if let Some(cache) = cache {
+            cache
+                .write_all_at(&buf, offset)
+                .context("Failed to write to cached ISO")?;
+        }
+        Ok(buf.len() as u64)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan_splits_parts_into_segments() {
+        let segments = plan(&[SEGMENT_LEN + 5, 3]);
+        let planned: Vec<_> = segments
+            .iter()
+            .map(|s| (s.part, s.start, s.end, s.offset))
+            .collect();
+        assert_eq!(
+            planned,
+            [
+                (0, 0, SEGMENT_LEN, 0),
+                (0, SEGMENT_LEN, SEGMENT_LEN + 5, SEGMENT_LEN),
+                (1, 0, 3, SEGMENT_LEN + 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_parts_have_no_segments() {
+        assert!(plan(&[0, 0]).is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+use crate::error::Error;
+use minisign_verify::{PublicKey, Signature};
+
+/// Minisign keys trusted to sign the distro catalog and ISO checksums.
+///
+/// The secret keys are held by the t2linux maintainers who publish the catalog, who sign it
+/// into `distro-metadata.json.minisig` next to it. None is pinned yet, so every signature
+/// fails with [`Error::NoTrustedKeys`] until their key is added.
+const TRUSTED_KEYS: &str = include_str!("../assets/trusted-keys.pub");
+/// Set to 1 to use data that has no signature at all, like a local catalog for testing
+pub const ALLOW_UNSIGNED_VAR: &str = "T2LINUX_ALLOW_UNSIGNED";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verified {
+    Signed,
+    /// There was no signature to check
+    Unsigned,
+}
+
+/// Checks the minisign `signature` of `data` against the trusted keys.
+///
+/// Data without a signature fails with [`Error::Unsigned`], unless `T2LINUX_ALLOW_UNSIGNED`
+/// lets it through as [`Verified::Unsigned`]. `what` names the data in errors.
+pub fn verify(what: &str, data: &[u8], signature: Option<&str>) -> Result<Verified, Error> {
+    let Some(signature) = signature else {
+        if std::env::var(ALLOW_UNSIGNED_VAR).is_ok_and(|v| v == "1") {
+            return Ok(Verified::Unsigned);
+        }
+        return Err(Error::Unsigned(what.to_owned()));
+    };
+    if trusted_keys().next().is_none() {
+        return Err(Error::NoTrustedKeys(what.to_owned()));
+    }
+    let bad_signature =
+        |e: minisign_verify::Error| Error::BadSignature(what.to_owned(), e.to_string());
+    let signature = Signature::decode(signature).map_err(bad_signature)?;
+    for key in trusted_keys() {
+        match key.verify(data, &signature, false) {
+            Ok(()) => return Ok(Verified::Signed),
+            Err(minisign_verify::Error::UnexpectedKeyId) => continue,
+            Err(e) => return Err(bad_signature(e)),
+        }
+    }
+    Err(Error::UntrustedKey(what.to_owned()))
+}
+
+fn trusted_keys() -> impl Iterator<Item = PublicKey> {
+    TRUSTED_KEYS
+        .lines()
+        .filter(|line| {
+            !line.is_empty() && !line.starts_with('#') && !line.starts_with("untrusted comment:")
+        })
+        .map(|line| PublicKey::from_base64(line).expect("Invalid trusted key"))
+}
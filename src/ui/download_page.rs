@@ -1,39 +1,74 @@
 use crate::{
-    install::{InstallProgress, InstallSettings},
+    fetch::RETRIES,
+    install::{InstallProgress, InstallSettings, IsoSource},
+    progress::ByteProgress,
     ui::app::{AppMessage, Page},
     ui::finish_page,
 };
 use anyhow::anyhow;
 use futures::StreamExt;
+use humansize::{DECIMAL, format_size};
 use iced::Length;
 use iced::alignment::Vertical;
-use iced::widget::{button, column, container, progress_bar, row, text};
+use iced::widget::{Space, button, column, container, progress_bar, row, text};
 use std::hash::{Hash, Hasher};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::fs::File;
 use tokio_util::sync::CancellationToken;
 
 use super::finish_page::FinishState;
 
+/// Width of the bar shown while the total is not known, and of the block moving in it
+const BUSY_WIDTH: f32 = 400.0;
+const BUSY_BLOCK: f32 = 100.0;
+/// Time the block takes to cross the bar
+const BUSY_CROSSING: Duration = Duration::from_millis(1200);
+
 #[derive(Debug)]
 pub struct DownloadPage {
     settings: InstallSettings,
-    progress: f64,
+    progress: Option<ByteProgress>,
     total_parts: Option<usize>,
     current_parts: Option<usize>,
+    corrupt_part: Option<usize>,
+    mirror: Option<String>,
+    /// Copying the ISO from the cache, instead of downloading it
+    cached: bool,
+    /// Reading back what was written
+    verifying: bool,
+    /// Attempt, Delay before it, until data arrives again
+    retry: Option<(usize, Duration)>,
+    /// Bytes per second the disk is written at
+    write_rate: Option<f64>,
+    /// Where the busy indicator is, from 0 to 1
+    busy: f32,
+    started: Instant,
     ct: CancellationToken,
     file: Arc<File>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum DownloadPageMessage {
+    Cached,
     /// total parts
     StartedIsoDownload(usize),
-    /// (part, progress)
-    DownloadProgress(usize, f64),
+    /// (part, progress of all parts)
+    DownloadProgress(usize, ByteProgress),
+    /// part
+    PartChecksumFailed(usize),
+    /// mirror url
+    Mirror(String),
+    /// (attempt, delay)
+    Retry(usize, Duration),
+    WriteProgress(ByteProgress),
+    VerifyStart,
+    VerifyProgress(ByteProgress),
     Finished,
     Failed(String),
     Cancel,
+    /// Moves the busy indicator
+    Tick(Instant),
 }
 
 impl DownloadPage {
@@ -41,12 +76,55 @@ impl DownloadPage {
         Self {
             total_parts: None,
             current_parts: None,
-            progress: 0.0,
+            corrupt_part: None,
+            mirror: None,
+            cached: false,
+            verifying: false,
+            retry: None,
+            write_rate: None,
+            busy: 0.0,
+            started: Instant::now(),
+            progress: None,
             settings,
             ct: CancellationToken::new(),
             file: Arc::new(file),
         }
     }
+
+    fn status(&self) -> String {
+        let Some(progress) = self.progress else {
+            return if self.verifying {
+                "Waiting for the disk to finish writing".to_owned()
+            } else {
+                "Starting download".to_owned()
+            };
+        };
+        progress.to_string()
+    }
+
+    /// Whether there is something going on that can't be shown as a fraction
+    fn is_busy(&self) -> bool {
+        self.progress
+            .is_none_or(|progress| progress.fraction().is_none())
+    }
+
+    /// A block going back and forth in a bar
+    fn busy_indicator(&self) -> iced::Element<'_, AppMessage> {
+        let block = container(Space::new())
+            .width(BUSY_BLOCK)
+            .height(Length::Fill)
+            .style(container::primary);
+        container(row![
+            Space::new().width(self.busy * (BUSY_WIDTH - BUSY_BLOCK)),
+            block
+        ])
+        .width(BUSY_WIDTH)
+        .height(10)
+        .style(|theme: &iced::Theme| {
+            container::background(theme.extended_palette().background.strong.color)
+        })
+        .into()
+    }
 }
 
 impl Page for DownloadPage {
@@ -55,7 +133,14 @@ impl Page for DownloadPage {
         let mut page: Option<Box<dyn Page>> = None;
         if let AppMessage::Download(msg) = message {
             match msg {
-                DownloadPageMessage::StartedIsoDownload(parts) => self.total_parts = Some(parts),
+                DownloadPageMessage::Cached => self.cached = true,
+                DownloadPageMessage::StartedIsoDownload(parts) => {
+                    // The cached copy was corrupt, so it is downloaded after all
+                    self.cached = false;
+                    self.progress = None;
+                    self.write_rate = None;
+                    self.total_parts = Some(parts);
+                }
                 DownloadPageMessage::Cancel => {
                     self.ct.cancel();
                 }
@@ -72,14 +157,39 @@ impl Page for DownloadPage {
                 }
                 DownloadPageMessage::DownloadProgress(part, progress) => {
                     self.current_parts = Some(part);
-                    self.progress = progress
+                    self.progress = Some(progress);
+                    self.retry = None;
+                }
+                DownloadPageMessage::PartChecksumFailed(part) => self.corrupt_part = Some(part),
+                DownloadPageMessage::Mirror(url) => self.mirror = Some(url),
+                DownloadPageMessage::Retry(attempt, delay) => self.retry = Some((attempt, delay)),
+                DownloadPageMessage::WriteProgress(progress) => {
+                    self.write_rate = Some(progress.rate).filter(|rate| *rate > 0.0)
+                }
+                DownloadPageMessage::VerifyStart => {
+                    self.verifying = true;
+                    self.progress = None;
+                    self.retry = None;
+                }
+                DownloadPageMessage::VerifyProgress(progress) => self.progress = Some(progress),
+                DownloadPageMessage::Tick(now) => {
+                    let crossings =
+                        (now - self.started).as_secs_f32() / BUSY_CROSSING.as_secs_f32();
+                    // Every other crossing goes back
+                    self.busy = 1.0 - (crossings % 2.0 - 1.0).abs();
                 }
             }
         }
         (page, command)
     }
     fn view(&self) -> iced::Element<'_, AppMessage> {
-        let mut row1 = row![text("Downloading ISO").size(24)]
+        let title = match self.settings.source() {
+            _ if self.verifying => "Verifying the disk".to_owned(),
+            IsoSource::Local(iso) => format!("Writing {}", iso.name()),
+            IsoSource::Distro(_) if self.cached => "Copying cached ISO".to_owned(),
+            IsoSource::Distro(_) => "Downloading ISO".to_owned(),
+        };
+        let mut row1 = row![text(title).size(24)]
             .spacing(16)
             .align_y(Vertical::Center);
         if let Some(total_parts) = self.total_parts
@@ -89,15 +199,46 @@ impl Page for DownloadPage {
             row1 = row1.push(text(format!("Part {current_parts} of {total_parts}")))
         }
         let mut col = column![row1,].spacing(16);
-        col = col.push(
-            row![
-                text(format!("{:.1}%", self.progress * 100.0)).width(50),
-                progress_bar(0.0..=100.0, self.progress as f32 * 100.0),
-            ]
-            .width(400)
-            .spacing(16)
-            .align_y(Vertical::Center),
-        );
+        if let Some(mirror) = &self.mirror {
+            let host = reqwest::Url::parse(mirror)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_owned))
+                .unwrap_or_else(|| mirror.clone());
+            col = col.push(text(format!("From {host}")));
+        }
+        if let Some(part) = self.corrupt_part {
+            col = col.push(text(format!(
+                "Part {part} was corrupted, downloading it again"
+            )));
+        }
+        if let Some((attempt, delay)) = self.retry {
+            col = col.push(text(format!(
+                "Connection lost, retrying in {}s (attempt {attempt} of {RETRIES})",
+                delay.as_secs()
+            )));
+        }
+        if let Some(fraction) = self.progress.and_then(|progress| progress.fraction()) {
+            col = col.push(
+                row![
+                    text(format!("{:.1}%", fraction * 100.0)).width(50),
+                    progress_bar(0.0..=100.0, fraction as f32 * 100.0),
+                ]
+                .width(400)
+                .spacing(16)
+                .align_y(Vertical::Center),
+            );
+        } else {
+            col = col.push(self.busy_indicator());
+        }
+        col = col.push(text(self.status()));
+        if let Some(rate) = self.write_rate
+            && !self.verifying
+        {
+            col = col.push(text(format!(
+                "Writing to the disk at {}/s",
+                format_size(rate as u64, DECIMAL)
+            )));
+        }
         col =
             col.push(button("Cancel").on_press(AppMessage::Download(DownloadPageMessage::Cancel)));
         container(col)
@@ -114,7 +255,13 @@ impl Page for DownloadPage {
             ct: self.ct.clone(),
             file: self.file.clone(),
         };
-        iced::Subscription::run_with(init, DownloadSubState::subscription_task)
+        let download = iced::Subscription::run_with(init, DownloadSubState::subscription_task);
+        if !self.is_busy() {
+            return download;
+        }
+        let tick = iced::time::every(Duration::from_millis(16))
+            .map(|now| AppMessage::Download(DownloadPageMessage::Tick(now)));
+        iced::Subscription::batch([download, tick])
     }
 }
 
@@ -135,12 +282,31 @@ impl DownloadSubState {
         self.settings
             .install(self.file.clone(), self.ct.clone())
             .map(|msg| match msg {
+                InstallProgress::IsoCached => AppMessage::Download(DownloadPageMessage::Cached),
                 InstallProgress::IsoDownloadStart(parts) => {
                     AppMessage::Download(DownloadPageMessage::StartedIsoDownload(parts))
                 }
                 InstallProgress::IsoDownloadProgress(part, progress) => {
                     AppMessage::Download(DownloadPageMessage::DownloadProgress(part, progress))
                 }
+                InstallProgress::IsoPartChecksumFailed(part) => {
+                    AppMessage::Download(DownloadPageMessage::PartChecksumFailed(part))
+                }
+                InstallProgress::IsoMirror(url) => {
+                    AppMessage::Download(DownloadPageMessage::Mirror(url))
+                }
+                InstallProgress::IsoRetry(attempt, delay) => {
+                    AppMessage::Download(DownloadPageMessage::Retry(attempt, delay))
+                }
+                InstallProgress::IsoWriteProgress(progress) => {
+                    AppMessage::Download(DownloadPageMessage::WriteProgress(progress))
+                }
+                InstallProgress::VerifyStart => {
+                    AppMessage::Download(DownloadPageMessage::VerifyStart)
+                }
+                InstallProgress::VerifyProgress(progress) => {
+                    AppMessage::Download(DownloadPageMessage::VerifyProgress(progress))
+                }
                 InstallProgress::Finished => AppMessage::Download(DownloadPageMessage::Finished),
                 InstallProgress::Failed(err) => {
                     println!("{err:#}");
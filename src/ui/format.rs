@@ -0,0 +1,68 @@
+//! Text for disks and images, the same in the window and on the command line
+
+use crate::{
+    disk::{BlockDevice, Partition},
+    install::{ImageSize, IsoSource},
+};
+use humansize::{DECIMAL, format_size};
+
+/// Name, size, bus and serial number, to pick a disk by
+pub fn disk_label(device: &BlockDevice) -> String {
+    let mut label = format!(
+        "{} · {} · {}",
+        device.name(),
+        format_size(device.size, DECIMAL),
+        device.transport
+    );
+    if let Some(serial) = &device.serial {
+        label.push_str(&format!(" · serial number {serial}"));
+    }
+    if device.read_only {
+        label.push_str(" · read-only");
+    }
+    label
+}
+
+/// The device path in front of [`disk_label`], as shown before the disk is erased
+pub fn disk_summary(device: &BlockDevice) -> String {
+    format!("/dev/{} · {}", device.os_identifier, disk_label(device))
+}
+
+/// Label, file system, size, and what is using the partition
+pub fn partition_summary(partition: &Partition) -> String {
+    let mut summary = partition.os_identifier.clone();
+    if let Some(label) = &partition.label {
+        summary.push_str(&format!(" \"{label}\""));
+    }
+    summary.push_str(&format!(
+        " · {} · {}",
+        partition.filesystem.as_deref().unwrap_or("unknown"),
+        format_size(partition.size, DECIMAL)
+    ));
+    if !partition.holders.is_empty() {
+        summary.push_str(&format!(" · unlocked as {}", partition.holders.join(", ")));
+    }
+    if !partition.mountpoints.is_empty() {
+        let mountpoints: Vec<String> = partition
+            .mountpoints
+            .iter()
+            .map(|mountpoint| mountpoint.display().to_string())
+            .collect();
+        summary.push_str(&format!(" · mounted on {}", mountpoints.join(", ")));
+    }
+    summary
+}
+
+/// How much space `source` takes on `device`
+pub fn image_size_summary(source: &IsoSource, device: &BlockDevice) -> String {
+    match source.image_size() {
+        Some(ImageSize::Exact(len)) => format!("{source} takes {}", format_size(len, DECIMAL)),
+        Some(ImageSize::AtLeast(len)) => {
+            format!("{source} takes at least {}", format_size(len, DECIMAL))
+        }
+        None => format!(
+            "The size of {source} is not known, so it can't be checked to fit on {}",
+            device.name()
+        ),
+    }
+}
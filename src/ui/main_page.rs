@@ -1,16 +1,20 @@
 use crate::{
+    catalog::{Catalog, CatalogSource},
     disk::{self, BlockDevice},
     distro::Distro,
-    install::{DownloadTarget, InstallSettings},
+    install::{DownloadTarget, InstallSettings, IsoSource},
+    local_iso::LocalIso,
     ui::{
         app::{AppMessage, Page},
-        download_page,
+        download_page, format,
     },
 };
 use anyhow::{Result, anyhow};
+use futures::StreamExt;
+use humansize::{DECIMAL, format_size};
 use iced::{
     Element, Length, Task,
-    widget::{button, center_x, column, radio, row, scrollable, space, text},
+    widget::{button, center_x, checkbox, column, radio, row, scrollable, space, text},
 };
 use std::{path::PathBuf, sync::Arc};
 use tokio::fs::{File, OpenOptions};
@@ -19,17 +23,32 @@ use super::finish_page::FinishPage;
 
 #[derive(Debug, Clone)]
 pub enum MainPageMessage {
-    LoadDistroList(Vec<Distro>),
+    LoadCatalog(Catalog),
     LoadBlockDeviceList(Vec<BlockDevice>),
     Err(Arc<anyhow::Error>),
     PickDistro(usize),
+    /// Index of the distro, and the size its parts add up to
+    ProbedSize(usize, Option<u64>),
+    TriggerIsoPicker,
+    /// ISO or IMG to flash, picked or dropped onto the window
+    CheckLocalIso(PathBuf),
+    PickLocalIso(Box<LocalIso>),
     OpenTargetPicker,
     OpenDistroPicker,
     TriggerFilePicker,
     PickIsoFile(Arc<File>, PathBuf),
     SetBlockDeviceFile(Arc<File>),
     TriggerBlockDevicePrompt,
-    PickBlockDeviceIndex(usize),
+    /// The disks as they are now, to open the picked one
+    PromptBlockDevice(Vec<BlockDevice>),
+    /// Unmount what is in use on the picked disk, and open it
+    ConfirmUnmount,
+    CancelUnmount,
+    /// Shows what is on the picked disk before it is erased
+    OpenConfirm,
+    ConfirmErase(bool),
+    /// Stable id of the disk
+    PickBlockDevice(String),
     StartInstall,
     Ignore,
 }
@@ -37,21 +56,38 @@ pub enum MainPageMessage {
 enum MainPageState {
     Distro,
     Target,
+    /// Erasing the picked disk
+    Confirm,
 }
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub enum UIDownloadTarget {
-    BlockDev(usize),
+    /// Stable id of the disk, which keeps pointing at it when the list changes
+    BlockDev(String),
     File(PathBuf),
 }
 
 pub struct MainPage {
     state: MainPageState,
     distro_list: Option<Vec<Distro>>,
+    catalog_source: Option<CatalogSource>,
+    /// The distro list had no signature to check
+    catalog_unsigned: bool,
     block_dev_list: Option<Vec<BlockDevice>>,
     distro_index: Option<usize>,
+    local_iso: Option<LocalIso>,
+    /// Local ISO that is being matched against the catalog
+    checking_iso: Option<PathBuf>,
     download_target: Option<UIDownloadTarget>,
     download_file: Option<File>,
+    /// Name of the picked disk, after it was unplugged
+    unplugged: Option<String>,
+    /// Asking before unmounting the picked disk
+    confirm_unmount: bool,
+    /// The user agreed to erase the picked disk
+    erase_confirmed: bool,
+    /// Distro whose size is being asked from its servers
+    probing_size: Option<usize>,
 }
 
 impl MainPage {
@@ -59,10 +95,18 @@ impl MainPage {
         Self {
             state: MainPageState::Distro,
             distro_list: None,
+            catalog_source: None,
+            catalog_unsigned: false,
             block_dev_list: None,
             distro_index: None,
+            local_iso: None,
+            checking_iso: None,
             download_target: None,
             download_file: None,
+            unplugged: None,
+            confirm_unmount: false,
+            erase_confirmed: false,
+            probing_size: None,
         }
     }
 }
@@ -73,26 +117,51 @@ impl Page for MainPage {
         let mut task = iced::Task::none();
         if let AppMessage::Main(msg) = message {
             match msg {
-                MainPageMessage::PickDistro(distro_index) => self.distro_index = Some(distro_index),
+                MainPageMessage::PickDistro(distro_index) => {
+                    self.distro_index = Some(distro_index);
+                    self.local_iso = None;
+                    if let Some(distro) = self
+                        .distro_list
+                        .as_ref()
+                        .and_then(|distros| distros.get(distro_index))
+                        && distro.download_size.is_none()
+                    {
+                        self.probing_size = Some(distro_index);
+                        task = probe_size(distro_index, distro.clone());
+                    }
+                }
+                MainPageMessage::ProbedSize(distro_index, size) => {
+                    if let Some(distro) = self
+                        .distro_list
+                        .as_mut()
+                        .and_then(|distros| distros.get_mut(distro_index))
+                    {
+                        distro.download_size = distro.download_size.or(size);
+                    }
+                    if self.probing_size == Some(distro_index) {
+                        self.probing_size = None;
+                    }
+                }
+                MainPageMessage::TriggerIsoPicker => task = pick_local_iso(),
+                MainPageMessage::CheckLocalIso(path) => {
+                    self.checking_iso = Some(path.clone());
+                    task = identify_local_iso(path, self.distro_list.clone().unwrap_or_default());
+                }
+                MainPageMessage::PickLocalIso(iso) => {
+                    // Another file might have been picked while this one was checked
+                    if self.checking_iso.as_ref() == Some(&iso.path) {
+                        self.checking_iso = None;
+                        self.distro_index = None;
+                        self.local_iso = Some(*iso);
+                    }
+                }
                 MainPageMessage::StartInstall => {
-                    if let Some(distro_index) = self.distro_index
-                        && let Some(download_target) = self.download_target.clone()
-                        && let Some(distro_list) = self.distro_list.clone()
-                        && let Some(block_dev_list) = self.block_dev_list.clone()
+                    if let Some(install_settings) = self.settings()
                         && self.download_file.is_some()
+                        && self.may_start(&install_settings)
                     {
                         // Already checked to be Some(), and behind a &mut so immutable
                         let file = self.download_file.take().unwrap();
-                        let download_target = match download_target {
-                            UIDownloadTarget::BlockDev(i) => {
-                                DownloadTarget::BlockDev(block_dev_list[i].clone())
-                            }
-                            UIDownloadTarget::File(path_buf) => DownloadTarget::File(path_buf),
-                        };
-                        let install_settings = InstallSettings::new(
-                            distro_list.get(distro_index).unwrap().clone(),
-                            download_target,
-                        );
                         page = Some(Box::new(download_page::DownloadPage::new(
                             install_settings,
                             file,
@@ -118,15 +187,26 @@ impl Page for MainPage {
                     self.download_file = Some(file);
                     self.download_target = Some(UIDownloadTarget::File(path_buf));
                 }
-                MainPageMessage::PickBlockDeviceIndex(i) => {
-                    self.download_target = Some(UIDownloadTarget::BlockDev(i))
+                MainPageMessage::PickBlockDevice(id) => {
+                    // The disk that was opened is not the one picked anymore
+                    if self.download_target != Some(UIDownloadTarget::BlockDev(id.clone())) {
+                        self.download_file = None;
+                    }
+                    self.download_target = Some(UIDownloadTarget::BlockDev(id));
+                    self.unplugged = None;
+                    self.confirm_unmount = false;
+                    self.erase_confirmed = false;
                 }
                 MainPageMessage::SetBlockDeviceFile(file) => {
                     let file = Arc::try_unwrap(file).unwrap();
                     self.download_file = Some(file);
                 }
                 MainPageMessage::Ignore => {}
-                MainPageMessage::LoadDistroList(distros) => self.distro_list = Some(distros),
+                MainPageMessage::LoadCatalog(catalog) => {
+                    self.distro_list = Some(catalog.distros);
+                    self.catalog_source = Some(catalog.source);
+                    self.catalog_unsigned = catalog.unsigned;
+                }
                 MainPageMessage::Err(error) => {
                     task = iced::Task::none();
                     page = Some(Box::new(FinishPage::new(
@@ -136,15 +216,33 @@ impl Page for MainPage {
                 MainPageMessage::OpenTargetPicker => {
                     self.state = MainPageState::Target;
                 }
-                MainPageMessage::LoadBlockDeviceList(l) => self.block_dev_list = Some(l),
+                MainPageMessage::LoadBlockDeviceList(list) => self.set_block_dev_list(list),
                 MainPageMessage::TriggerBlockDevicePrompt => {
-                    if let Some(block_devices) = &self.block_dev_list
-                        && let Some(UIDownloadTarget::BlockDev(i)) = self.download_target
-                    {
-                        let block_device = block_devices[i].clone();
-                        task = get_block_dev_fd(block_device);
+                    // What is mounted changes without the list being loaded again
+                    task = get_block_dev_list_then(MainPageMessage::PromptBlockDevice);
+                }
+                MainPageMessage::PromptBlockDevice(list) => {
+                    self.set_block_dev_list(list);
+                    if let Some(block_device) = self.picked_block_dev() {
+                        if block_device.partitions_in_use().next().is_some() {
+                            self.confirm_unmount = true;
+                        } else {
+                            task = get_block_dev_fd(block_device.clone());
+                        }
                     }
                 }
+                MainPageMessage::ConfirmUnmount => {
+                    self.confirm_unmount = false;
+                    if let Some(block_device) = self.picked_block_dev() {
+                        task = unmount_block_dev(block_device.clone());
+                    }
+                }
+                MainPageMessage::CancelUnmount => self.confirm_unmount = false,
+                MainPageMessage::OpenConfirm => {
+                    self.erase_confirmed = false;
+                    self.state = MainPageState::Confirm;
+                }
+                MainPageMessage::ConfirmErase(confirmed) => self.erase_confirmed = confirmed,
                 MainPageMessage::OpenDistroPicker => {
                     self.state = MainPageState::Distro;
                 }
@@ -159,12 +257,22 @@ impl Page for MainPage {
         let e = match self.state {
             MainPageState::Distro => self.distro_picker_view(),
             MainPageState::Target => self.target_picker_view(),
+            MainPageState::Confirm => self.confirm_view(),
         };
         e.into()
     }
 
     fn subscription(&self) -> iced::Subscription<AppMessage> {
-        iced::Subscription::none()
+        let events = match self.state {
+            MainPageState::Distro => iced::event::listen_with(|event, _, _| match event {
+                iced::Event::Window(iced::window::Event::FileDropped(path)) => {
+                    Some(AppMessage::Main(MainPageMessage::CheckLocalIso(path)))
+                }
+                _ => None,
+            }),
+            MainPageState::Target | MainPageState::Confirm => iced::Subscription::none(),
+        };
+        iced::Subscription::batch([events, iced::Subscription::run(watch_block_devs)])
     }
 }
 
@@ -172,21 +280,113 @@ impl MainPage {
     pub fn init_tasks() -> Task<AppMessage> {
         Task::batch([get_distro_list(), get_block_dev_list()])
     }
+    fn set_block_dev_list(&mut self, list: Vec<BlockDevice>) {
+        if let Some(picked) = self.picked_block_dev()
+            && !list.iter().any(|dev| dev.id == picked.id)
+        {
+            self.unplugged = Some(picked.name());
+            self.download_target = None;
+            self.download_file = None;
+            self.confirm_unmount = false;
+            if let MainPageState::Confirm = self.state {
+                self.state = MainPageState::Target;
+            }
+        }
+        self.block_dev_list = Some(list);
+    }
+    fn picked_block_dev(&self) -> Option<&BlockDevice> {
+        let Some(UIDownloadTarget::BlockDev(id)) = &self.download_target else {
+            return None;
+        };
+        self.block_dev_list
+            .as_ref()?
+            .iter()
+            .find(|dev| &dev.id == id)
+    }
+    fn settings(&self) -> Option<InstallSettings> {
+        Some(InstallSettings::new(self.source()?, self.target()?))
+    }
+    /// Disks are only erased once the user agreed to, and when they can be written to
+    fn may_start(&self, settings: &InstallSettings) -> bool {
+        match settings.download_target() {
+            DownloadTarget::BlockDev(_) => {
+                self.erase_confirmed && !self.is_probing_size() && settings.check_target().is_ok()
+            }
+            DownloadTarget::File(_) => true,
+        }
+    }
+    fn target(&self) -> Option<DownloadTarget> {
+        match self.download_target.as_ref()? {
+            UIDownloadTarget::BlockDev(_) => {
+                Some(DownloadTarget::BlockDev(self.picked_block_dev()?.clone()))
+            }
+            UIDownloadTarget::File(path_buf) => Some(DownloadTarget::File(path_buf.clone())),
+        }
+    }
+    /// The picked distro's size is still being asked for, which the disk is checked against
+    fn is_probing_size(&self) -> bool {
+        self.local_iso.is_none()
+            && self.probing_size.is_some()
+            && self.probing_size == self.distro_index
+    }
+    fn source(&self) -> Option<IsoSource> {
+        if let Some(iso) = &self.local_iso {
+            return Some(IsoSource::Local(iso.clone()));
+        }
+        let distro = self.distro_list.as_ref()?.get(self.distro_index?)?;
+        Some(IsoSource::Distro(distro.clone()))
+    }
     fn distro_picker_view(&self) -> iced::widget::Column<'_, AppMessage> {
         let mut distro_list = column![].spacing(16);
         if let Some(distros) = &self.distro_list {
             for (i, distro) in distros.iter().enumerate() {
-                distro_list =
-                    distro_list.push(radio(distro.name.clone(), i, self.distro_index, |_| {
-                        AppMessage::Main(MainPageMessage::PickDistro(i))
-                    }));
+                let label = match &distro.version {
+                    Some(version) => format!("{} {version}", distro.name),
+                    None => distro.name.clone(),
+                };
+                let mut entry = column![radio(label, i, self.distro_index, |_| {
+                    AppMessage::Main(MainPageMessage::PickDistro(i))
+                })]
+                .spacing(4);
+                let summary = distro_summary(distro);
+                if !summary.is_empty() {
+                    entry = entry.push(text(summary).size(14));
+                }
+                distro_list = distro_list.push(entry);
+            }
+        }
+        let mut header = column![text("Choose a distro").size(24).height(Length::Shrink)];
+        match &self.catalog_source {
+            Some(CatalogSource::Cache(e)) => {
+                header = header.push(text(
+                    "Could not fetch the distro list, showing a saved copy",
+                ));
+                header = header.push(text(e.root_cause().to_string()).size(14));
+            }
+            Some(CatalogSource::BuiltIn(e)) => {
+                header = header.push(text(
+                    "Could not fetch the distro list, showing the list included with the installer",
+                ));
+                header = header.push(text(e.root_cause().to_string()).size(14));
+            }
+            Some(CatalogSource::Local(path)) => {
+                header = header.push(text(format!("Distro list from {}", path.display())))
             }
+            Some(CatalogSource::Remote) | None => {}
+        }
+        if self.catalog_unsigned {
+            header = header.push(
+                text("The distro list is not signed, so where it downloads from is not checked")
+                    .size(14),
+            );
         }
         column![
             center_x(
                 column![
-                    text("Choose a distro").size(24).height(Length::Shrink),
-                    scrollable(distro_list).height(Length::Fill)
+                    header.spacing(8),
+                    scrollable(distro_list).height(Length::Fill),
+                    self.distro_details_view(),
+                    self.local_iso_view(),
                 ]
                 .spacing(16)
             )
@@ -203,6 +403,42 @@ impl MainPage {
         // .spacing(16)
         .padding(16)
     }
+    fn distro_details_view(&self) -> Element<'_, AppMessage> {
+        let mut col = column![].spacing(8);
+        if let Some(i) = self.distro_index
+            && let Some(distro) = self.distro_list.as_ref().and_then(|l| l.get(i))
+        {
+            if let Some(description) = &distro.description {
+                col = col.push(text(description));
+            }
+            if let Some(homepage) = &distro.homepage {
+                col = col.push(text(format!("More information: {homepage}")));
+            }
+            if let Some(notes) = &distro.install_notes {
+                col = col.push(text(format!("Before installing: {notes}")));
+            }
+        }
+        col.into()
+    }
+    fn local_iso_view(&self) -> Element<'_, AppMessage> {
+        let status = if let Some(path) = &self.checking_iso {
+            format!("Checking {}", path.display())
+        } else if let Some(iso) = &self.local_iso {
+            match &iso.distro {
+                Some(distro) => format!("{}, which is {}", iso.name(), distro.name),
+                None => format!("{}, which is not in the distro list", iso.name()),
+            }
+        } else {
+            "Or flash an ISO or IMG file you already have, or drop it here".to_owned()
+        };
+        row![
+            text(status),
+            space::horizontal(),
+            button("Choose ISO file").on_press(AppMessage::Main(MainPageMessage::TriggerIsoPicker)),
+        ]
+        .spacing(16)
+        .into()
+    }
     fn target_picker_view(&self) -> iced::widget::Column<'_, AppMessage> {
         column![
             center_x(column![self.file_path_view(), self.block_dev_view()].spacing(32)),
@@ -210,11 +446,18 @@ impl MainPage {
             row![
                 button("Back").on_press(AppMessage::Main(MainPageMessage::OpenDistroPicker)),
                 space::horizontal(),
-                button("Begin Download").on_press_maybe(if self.download_file.is_some() {
-                    Some(AppMessage::Main(MainPageMessage::StartInstall))
-                } else {
-                    None
-                })
+                match self.download_target {
+                    Some(UIDownloadTarget::BlockDev(_)) => button("Continue").on_press_maybe(
+                        self.download_file
+                            .is_some()
+                            .then_some(AppMessage::Main(MainPageMessage::OpenConfirm))
+                    ),
+                    _ => button("Begin Download").on_press_maybe(
+                        self.download_file
+                            .is_some()
+                            .then_some(AppMessage::Main(MainPageMessage::StartInstall))
+                    ),
+                }
             ]
             .height(Length::Fixed(32.0)),
         ]
@@ -233,35 +476,132 @@ impl MainPage {
         );
         col.spacing(16).into()
     }
+    fn confirm_view(&self) -> iced::widget::Column<'_, AppMessage> {
+        let mut col = column![].spacing(16);
+        let settings = self.settings();
+        if let Some(settings) = &settings
+            && let DownloadTarget::BlockDev(device) = settings.download_target()
+        {
+            col =
+                col.push(text(format!("Everything on {} will be erased", device.name())).size(24));
+            col = col.push(text(format::disk_summary(device)));
+            let mut partitions = column![].spacing(4);
+            if device.partitions.is_empty() {
+                partitions = partitions.push(text("No partitions"));
+            } else {
+                partitions = partitions.push(text("Partitions:"));
+                for partition in &device.partitions {
+                    partitions =
+                        partitions.push(text(format::partition_summary(partition)).size(14));
+                }
+            }
+            col = col.push(partitions);
+            col = col.push(text(if self.is_probing_size() {
+                format!("Finding out the size of {}", settings.source())
+            } else {
+                format::image_size_summary(settings.source(), device)
+            }));
+            col = col.push(match settings.check_target() {
+                Err(e) => Element::from(text(format!("Can't write to this disk: {e}"))),
+                Ok(()) => checkbox(self.erase_confirmed)
+                    .label(format!(
+                        "I understand that everything on {} will be lost",
+                        device.name()
+                    ))
+                    .on_toggle(|confirmed| {
+                        AppMessage::Main(MainPageMessage::ConfirmErase(confirmed))
+                    })
+                    .into(),
+            });
+        }
+        column![
+            center_x(col),
+            space::vertical(),
+            row![
+                button("Back").on_press(AppMessage::Main(MainPageMessage::OpenTargetPicker)),
+                space::horizontal(),
+                button("Erase and Write").on_press_maybe(
+                    settings
+                        .filter(|settings| self.may_start(settings))
+                        .map(|_| AppMessage::Main(MainPageMessage::StartInstall))
+                ),
+            ]
+            .height(Length::Fixed(32.0)),
+        ]
+        .padding(16)
+    }
     fn block_dev_view(&self) -> Element<'_, AppMessage> {
-        let selected_i = if let Some(UIDownloadTarget::BlockDev(n)) = self.download_target {
-            Some(n)
-        } else {
-            None
-        };
+        let picked = self.picked_block_dev().map(|dev| &dev.id);
         let mut list = column![].spacing(16);
+        if let Some(name) = &self.unplugged {
+            list = list.push(text(format!("{name} was unplugged, pick a disk again")));
+        }
         if let Some(devs) = &self.block_dev_list {
             for (cur_i, dev) in devs.iter().enumerate() {
-                let label = format!("{} ({})", dev.name, dev.size);
-                list = list.push(radio(label, cur_i, selected_i, |_| {
-                    AppMessage::Main(MainPageMessage::PickBlockDeviceIndex(cur_i))
-                }));
+                let label = format::disk_label(dev);
+                let selected_i = (picked == Some(&dev.id)).then_some(cur_i);
+                let mut entry = column![].spacing(4);
+                // Writing would only fail, so they can't be picked
+                entry = if dev.read_only {
+                    entry.push(text(format!("{label} · read-only")))
+                } else {
+                    entry.push(radio(label, cur_i, selected_i, |_| {
+                        AppMessage::Main(MainPageMessage::PickBlockDevice(dev.id.clone()))
+                    }))
+                };
+                for partition in &dev.partitions {
+                    entry = entry.push(text(format::partition_summary(partition)).size(14));
+                }
+                list = list.push(entry);
             }
         }
-        column![
+        let mut col = column![
             text("Flash to a disk").size(24),
             scrollable(list).height(Length::Shrink),
-            button("Open Device").on_press_maybe(match self.download_target {
-                Some(UIDownloadTarget::BlockDev(_)) =>
-                    Some(AppMessage::Main(MainPageMessage::TriggerBlockDevicePrompt)),
-                _ => None,
-            })
         ]
-        .spacing(16)
-        .into()
+        .spacing(16);
+        match self.picked_block_dev() {
+            Some(block_device) if self.confirm_unmount => {
+                col = col.push(text(format!(
+                    "These are in use on {}, and will be unmounted and locked:",
+                    block_device.name()
+                )));
+                for partition in block_device.partitions_in_use() {
+                    col = col.push(text(format::partition_summary(partition)).size(14));
+                }
+                col = col.push(
+                    row![
+                        button("Unmount and Open")
+                            .on_press(AppMessage::Main(MainPageMessage::ConfirmUnmount)),
+                        button("Cancel").on_press(AppMessage::Main(MainPageMessage::CancelUnmount)),
+                    ]
+                    .spacing(8),
+                );
+            }
+            picked => {
+                col = col.push(button("Open Device").on_press_maybe(
+                    picked.map(|_| AppMessage::Main(MainPageMessage::TriggerBlockDevicePrompt)),
+                ));
+            }
+        }
+        col.into()
     }
 }
 
+/// Release date, architecture, desktop and size, as far as the catalog has them
+fn distro_summary(distro: &Distro) -> String {
+    [
+        distro.release_date.clone(),
+        distro.architecture.clone(),
+        distro.desktop_environment.clone(),
+        distro.download_size.map(|size| format_size(size, DECIMAL)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(" · ")
+}
+
 fn open_file(name: String) -> Task<AppMessage> {
     Task::future(async {
         if let Some(handle) = rfd::AsyncFileDialog::new()
@@ -274,7 +614,8 @@ fn open_file(name: String) -> Task<AppMessage> {
                 .read(true)
                 .write(true)
                 .create(true)
-                .truncate(true)
+                // Left to the installer, which can resume into an existing file
+                .truncate(false)
                 .open(handle.path())
                 .await?;
             Ok(Some((
@@ -300,20 +641,85 @@ fn open_file(name: String) -> Task<AppMessage> {
         }
     })
 }
+fn pick_local_iso() -> Task<AppMessage> {
+    Task::future(
+        rfd::AsyncFileDialog::new()
+            .add_filter("Disk images", &["iso", "img"])
+            .add_filter("All files", &["*"])
+            .pick_file(),
+    )
+    .then(|handle| match handle {
+        Some(handle) => Task::done(AppMessage::Main(MainPageMessage::CheckLocalIso(
+            handle.path().to_owned(),
+        ))),
+        None => Task::done(AppMessage::Main(MainPageMessage::Ignore)),
+    })
+}
+
+fn identify_local_iso(path: PathBuf, distros: Vec<Distro>) -> Task<AppMessage> {
+    Task::future(async move { LocalIso::identify(path, &distros).await }).then(
+        |handle| match handle {
+            Ok(iso) => Task::done(AppMessage::Main(MainPageMessage::PickLocalIso(Box::new(
+                iso,
+            )))),
+            Err(e) => Task::done(AppMessage::Main(MainPageMessage::Err(Arc::new(e)))),
+        },
+    )
+}
+
 fn get_distro_list() -> Task<AppMessage> {
-    Task::future(Distro::get_all()).then(|handle| match handle {
-        Ok(distros) => Task::done(AppMessage::Main(MainPageMessage::LoadDistroList(distros))),
+    Task::future(Catalog::load()).then(|handle| match handle {
+        Ok(catalog) => Task::done(AppMessage::Main(MainPageMessage::LoadCatalog(catalog))),
         Err(e) => Task::done(AppMessage::Main(MainPageMessage::Err(Arc::new(e)))),
     })
 }
 
+fn probe_size(distro_index: usize, mut distro: Distro) -> Task<AppMessage> {
+    Task::future(async move {
+        distro.probe_size().await;
+        AppMessage::Main(MainPageMessage::ProbedSize(
+            distro_index,
+            distro.download_size,
+        ))
+    })
+}
+
 fn get_block_dev_list() -> Task<AppMessage> {
-    Task::future(disk::get_external_disks()).then(|handle| match handle {
-        Ok(list) => Task::done(AppMessage::Main(MainPageMessage::LoadBlockDeviceList(list))),
+    get_block_dev_list_then(MainPageMessage::LoadBlockDeviceList)
+}
+
+fn get_block_dev_list_then(message: fn(Vec<BlockDevice>) -> MainPageMessage) -> Task<AppMessage> {
+    Task::future(disk::get_external_disks()).then(move |handle| match handle {
+        Ok(list) => Task::done(AppMessage::Main(message(list))),
         Err(e) => Task::done(AppMessage::Main(MainPageMessage::Err(Arc::new(e)))),
     })
 }
 
+fn unmount_block_dev(b: BlockDevice) -> Task<AppMessage> {
+    let open = async move {
+        disk::unmount_disk(&b).await?;
+        disk::get_fd_for_disk(b).await
+    };
+    Task::future(open).then(|handle| match handle {
+        Ok(file) => Task::done(AppMessage::Main(MainPageMessage::SetBlockDeviceFile(
+            Arc::new(file),
+        ))),
+        Err(e) => Task::done(AppMessage::Main(MainPageMessage::Err(Arc::new(e)))),
+    })
+}
+
+/// Keeps the list of disks up to date as they are plugged in and unplugged
+fn watch_block_devs() -> impl futures::Stream<Item = AppMessage> {
+    disk::watch_external_disks().map(|list| match list {
+        Ok(list) => AppMessage::Main(MainPageMessage::LoadBlockDeviceList(list)),
+        Err(e) => {
+            // The list from the start stays usable
+            println!("Failed to watch for disks: {e:#}");
+            AppMessage::Main(MainPageMessage::Ignore)
+        }
+    })
+}
+
 fn get_block_dev_fd(b: BlockDevice) -> Task<AppMessage> {
     Task::future(disk::get_fd_for_disk(b)).then(|handle| match handle {
         Ok(file) => Task::done(AppMessage::Main(MainPageMessage::SetBlockDeviceFile(
@@ -0,0 +1,111 @@
+use crate::device_writer;
+use crate::error::Error;
+use anyhow::{Context, anyhow};
+use iced::task::{Straw, sipper};
+use sha2::{Digest, Sha256, digest::Output};
+use std::{
+    fs::File,
+    io::{self, Write},
+    os::unix::fs::FileExt,
+    sync::Arc,
+};
+use tokio_util::sync::CancellationToken;
+
+/// Amount read back at once
+const READ_LEN: u64 = 4 << 20;
+
+/// What was written to the target, to check it against afterwards
+#[derive(Debug, Clone, PartialEq)]
+pub struct Written {
+    pub len: u64,
+    pub sha256: Output<Sha256>,
+}
+
+/// Writer that keeps track of everything written through it
+pub struct HashWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<W: Write> HashWriter<W> {
+    /// Continues after `len` bytes, which are already in `hasher`
+    pub fn resume(inner: W, hasher: Sha256, len: u64) -> Self {
+        Self { inner, hasher, len }
+    }
+
+    pub fn written(self) -> Written {
+        Written {
+            len: self.len,
+            sha256: self.hasher.finalize(),
+        }
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.hasher.update(&buf[..len]);
+        self.len += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads back what was written into `file`, from the device itself, and compares it.
+///
+/// Sends the number of bytes read back so far.
+pub fn read_back(
+    file: Arc<tokio::fs::File>,
+    written: Written,
+    ct: CancellationToken,
+) -> impl Straw<(), u64, anyhow::Error> {
+    sipper(async move |mut sender| {
+        let file = Arc::new(file.try_clone().await?.into_std().await);
+        let f = file.clone();
+        tokio::task::spawn_blocking(move || drop_caches(&f))
+            .await?
+            .context("Failed to flush the written data")?;
+        let mut hasher = Sha256::new();
+        let mut done = 0;
+        while done < written.len {
+            if ct.is_cancelled() {
+                return Err(anyhow!("Verification cancelled"));
+            }
+            let len = READ_LEN.min(written.len - done);
+            let f = file.clone();
+            let buf = tokio::task::spawn_blocking(move || {
+                let mut buf = vec![0; len as usize];
+                f.read_exact_at(&mut buf, done).map(|_| buf)
+            })
+            .await?
+            .context("Failed to read back the written data")?;
+            hasher.update(&buf);
+            done += len;
+            sender.send(done).await;
+        }
+        if hasher.finalize() != written.sha256 {
+            return Err(Error::VerifyMismatch.into());
+        }
+        Ok(())
+    })
+}
+
+/// Writes out everything, and makes sure it is read from the device again, instead of
+/// from what the kernel still has cached.
+pub fn drop_caches(file: &File) -> io::Result<()> {
+    device_writer::sync_device(file)?;
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        // Without CAP_SYS_ADMIN flushing the device's buffers fails, so this has to do
+        let ret = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+        if ret != 0 {
+            return Err(io::Error::from_raw_os_error(ret));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,338 @@
+use std::{
+    fs::File,
+    io::{self, Seek, SeekFrom},
+    os::unix::fs::FileExt,
+};
+
+/// Sizes GPT can be laid out in, it does not say which itself
+const SECTOR_SIZES: [u64; 2] = [512, 4096];
+const GPT_MAGIC: &[u8] = b"EFI PART";
+const MBR_MAGIC: &[u8] = &[0x55, 0xaa];
+const MBR_MAGIC_OFFSET: u64 = 510;
+/// Partition tables larger than this are not real ones
+const MAX_GPT_ENTRIES_LEN: u64 = 1 << 20;
+
+/// Magic bytes that give away a file system, and where they are from its start
+struct Magic {
+    name: &'static str,
+    offset: u64,
+    bytes: &'static [u8],
+}
+
+const FILESYSTEMS: &[Magic] = &[
+    Magic {
+        name: "ext4",
+        offset: 0x438,
+        bytes: &[0x53, 0xef],
+    },
+    Magic {
+        name: "btrfs",
+        offset: 0x10040,
+        bytes: b"_BHRfS_M",
+    },
+    Magic {
+        name: "xfs",
+        offset: 0,
+        bytes: b"XFSB",
+    },
+    Magic {
+        name: "vfat",
+        offset: 0x36,
+        bytes: b"FAT12   ",
+    },
+    Magic {
+        name: "vfat",
+        offset: 0x36,
+        bytes: b"FAT16   ",
+    },
+    Magic {
+        name: "vfat",
+        offset: 0x52,
+        bytes: b"FAT32   ",
+    },
+    Magic {
+        name: "exfat",
+        offset: 3,
+        bytes: b"EXFAT   ",
+    },
+    Magic {
+        name: "ntfs",
+        offset: 3,
+        bytes: b"NTFS    ",
+    },
+    Magic {
+        name: "hfsplus",
+        offset: 0x400,
+        bytes: b"H+",
+    },
+    Magic {
+        name: "hfsplus",
+        offset: 0x400,
+        bytes: b"HX",
+    },
+    Magic {
+        name: "apfs",
+        offset: 0x20,
+        bytes: b"NXSB",
+    },
+    Magic {
+        name: "iso9660",
+        offset: 0x8001,
+        bytes: b"CD001",
+    },
+    Magic {
+        name: "crypto_LUKS",
+        offset: 0,
+        bytes: b"LUKS\xba\xbe",
+    },
+    Magic {
+        name: "swap",
+        offset: 0xff6,
+        bytes: b"SWAPSPACE2",
+    },
+];
+
+/// Magic bytes found in `file`
+struct Found {
+    name: &'static str,
+    offset: u64,
+    len: usize,
+}
+
+/// Clears the partition tables and file system signatures in `file`, so nothing of its old
+/// layout is found next to the image written over it.
+///
+/// Only the magic bytes are zeroed, like `wipefs` does. Returns the names of what was cleared.
+pub fn wipe(file: &File) -> io::Result<Vec<&'static str>> {
+    let len = len(file)?;
+    let mut found = vec![];
+    let mut starts = vec![0];
+    if matches(file, MBR_MAGIC_OFFSET, MBR_MAGIC)? {
+        found.push(Found {
+            name: "dos",
+            offset: MBR_MAGIC_OFFSET,
+            len: MBR_MAGIC.len(),
+        });
+        starts.extend(mbr_partitions(file)?);
+    }
+    for sector in SECTOR_SIZES {
+        // The backup is at the end of the disk, and survives writing a smaller image
+        for header in [sector, len.saturating_sub(sector)] {
+            if header == 0 || !matches(file, header, GPT_MAGIC)? {
+                continue;
+            }
+            found.push(Found {
+                name: "gpt",
+                offset: header,
+                len: GPT_MAGIC.len(),
+            });
+            starts.extend(gpt_partitions(file, header, sector)?);
+        }
+    }
+    starts.sort_unstable();
+    starts.dedup();
+    for start in starts.into_iter().filter(|start| *start < len) {
+        // Boot sectors of old partitions are taken for partition tables too
+        if start != 0 && matches(file, start + MBR_MAGIC_OFFSET, MBR_MAGIC)? {
+            found.push(Found {
+                name: "dos",
+                offset: start + MBR_MAGIC_OFFSET,
+                len: MBR_MAGIC.len(),
+            });
+        }
+        for magic in FILESYSTEMS {
+            if matches(file, start + magic.offset, magic.bytes)? {
+                found.push(Found {
+                    name: magic.name,
+                    offset: start + magic.offset,
+                    len: magic.bytes.len(),
+                });
+            }
+        }
+    }
+    for found in &found {
+        file.write_all_at(&vec![0; found.len], found.offset)?;
+    }
+    if !found.is_empty() {
+        file.sync_all()?;
+    }
+    let mut names: Vec<_> = found.iter().map(|found| found.name).collect();
+    names.sort_unstable();
+    names.dedup();
+    Ok(names)
+}
+
+/// Length of `file`, which for a block device is only known by seeking to its end
+fn len(mut file: &File) -> io::Result<u64> {
+    let position = file.stream_position()?;
+    let len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(position))?;
+    Ok(len)
+}
+
+/// Offsets of the partitions in the MBR
+fn mbr_partitions(file: &File) -> io::Result<Vec<u64>> {
+    let Some(table) = read(file, 446, 64)? else {
+        return Ok(vec![]);
+    };
+    Ok(table
+        .chunks_exact(16)
+        // Empty entries, and the one protecting a GPT
+        .filter(|entry| entry[4] != 0 && entry[4] != 0xee)
+        .map(|entry| u64::from(u32::from_le_bytes(entry[8..12].try_into().unwrap())) * 512)
+        .collect())
+}
+
+/// Offsets of the partitions listed by the GPT header at `header`
+fn gpt_partitions(file: &File, header: u64, sector: u64) -> io::Result<Vec<u64>> {
+    let Some(header) = read(file, header, 92)? else {
+        return Ok(vec![]);
+    };
+    let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+    let count = u64::from(u32::from_le_bytes(header[80..84].try_into().unwrap()));
+    let entry_len = u64::from(u32::from_le_bytes(header[84..88].try_into().unwrap()));
+    if entry_len < 128 || count * entry_len > MAX_GPT_ENTRIES_LEN {
+        return Ok(vec![]);
+    }
+    let Some(entries_offset) = entries_lba.checked_mul(sector) else {
+        return Ok(vec![]);
+    };
+    let Some(entries) = read(file, entries_offset, (count * entry_len) as usize)? else {
+        return Ok(vec![]);
+    };
+    Ok(entries
+        .chunks_exact(entry_len as usize)
+        // Unused entries have no type
+        .filter(|entry| entry[..16].iter().any(|b| *b != 0))
+        .filter_map(|entry| {
+            u64::from_le_bytes(entry[32..40].try_into().unwrap()).checked_mul(sector)
+        })
+        .collect())
+}
+
+/// `len` bytes of `file` at `offset`, if it is that long
+fn read(file: &File, offset: u64, len: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut buf = vec![0; len];
+    match file.read_exact_at(&mut buf, offset) {
+        Ok(()) => Ok(Some(buf)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn matches(file: &File, offset: u64, bytes: &[u8]) -> io::Result<bool> {
+    Ok(read(file, offset, bytes.len())?.is_some_and(|buf| buf == bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Write, ops::Range};
+
+    const LEN: usize = 1 << 20;
+
+    fn put(image: &mut [u8], offset: u64, bytes: &[u8]) {
+        let offset = offset as usize;
+        image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn mbr_entry(image: &mut [u8], index: u64, kind: u8, lba: u32) {
+        let entry = 446 + index * 16;
+        put(image, entry + 4, &[kind]);
+        put(image, entry + 8, &lba.to_le_bytes());
+        put(image, MBR_MAGIC_OFFSET, MBR_MAGIC);
+    }
+
+    /// GPT header at `header`, listing partitions starting at each of `starts` LBAs
+    fn gpt(image: &mut [u8], header: u64, sector: u64, entries_lba: u64, starts: &[u64]) {
+        put(image, header, GPT_MAGIC);
+        put(image, header + 72, &entries_lba.to_le_bytes());
+        put(image, header + 80, &(starts.len() as u32).to_le_bytes());
+        put(image, header + 84, &128u32.to_le_bytes());
+        for (i, start) in starts.iter().enumerate() {
+            let entry = entries_lba * sector + i as u64 * 128;
+            put(image, entry, &[0xaf; 16]);
+            put(image, entry + 32, &start.to_le_bytes());
+        }
+    }
+
+    /// Wipes `image`, returning what was found, and the ranges of it that were changed
+    fn wipe_image(image: &[u8]) -> (Vec<&'static str>, Vec<Range<usize>>) {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(image).unwrap();
+        let names = wipe(&file).unwrap();
+        let mut wiped = vec![0; image.len()];
+        file.read_exact_at(&mut wiped, 0).unwrap();
+        let mut changed: Vec<Range<usize>> = vec![];
+        for i in (0..image.len()).filter(|i| image[*i] != wiped[*i]) {
+            assert_eq!(wiped[i], 0);
+            match changed.last_mut() {
+                Some(range) if range.end == i => range.end = i + 1,
+                _ => changed.push(i..i + 1),
+            }
+        }
+        (names, changed)
+    }
+
+    #[test]
+    fn mbr_with_partitions() {
+        let mut image = vec![0; LEN];
+        mbr_entry(&mut image, 0, 0x83, 64);
+        mbr_entry(&mut image, 1, 0x0c, 512);
+        put(&mut image, 64 * 512 + 0x438, &[0x53, 0xef]);
+        put(&mut image, 512 * 512 + 0x52, b"FAT32   ");
+        put(&mut image, 512 * 512 + MBR_MAGIC_OFFSET, MBR_MAGIC);
+        let (names, changed) = wipe_image(&image);
+        assert_eq!(names, ["dos", "ext4", "vfat"]);
+        assert_eq!(
+            changed,
+            [510..512, 0x8438..0x843a, 0x40052..0x4005a, 0x401fe..0x40200,]
+        );
+    }
+
+    #[test]
+    fn gpt_with_backup() {
+        let mut image = vec![0; LEN];
+        mbr_entry(&mut image, 0, 0xee, 1);
+        gpt(&mut image, 512, 512, 2, &[64]);
+        let backup = LEN as u64 - 512;
+        gpt(&mut image, backup, 512, backup / 512 - 32, &[64]);
+        put(&mut image, 64 * 512, b"XFSB");
+        let (names, changed) = wipe_image(&image);
+        assert_eq!(names, ["dos", "gpt", "xfs"]);
+        // The protective MBR's magic runs straight into the GPT header's
+        assert_eq!(changed, [510..520, 0x8000..0x8004, LEN - 512..LEN - 504]);
+    }
+
+    #[test]
+    fn backup_gpt_left_by_a_smaller_image() {
+        let mut image = vec![0; LEN];
+        let backup = LEN as u64 - 512;
+        gpt(&mut image, backup, 512, backup / 512 - 32, &[1024]);
+        put(&mut image, 1024 * 512 + 0x8001, b"CD001");
+        let (names, changed) = wipe_image(&image);
+        assert_eq!(names, ["gpt", "iso9660"]);
+        assert_eq!(changed, [0x88001..0x88006, LEN - 512..LEN - 504]);
+    }
+
+    #[test]
+    fn gpt_with_4k_sectors() {
+        let mut image = vec![0; LEN];
+        gpt(&mut image, 4096, 4096, 2, &[16]);
+        let backup = LEN as u64 - 4096;
+        gpt(&mut image, backup, 4096, backup / 4096 - 4, &[16]);
+        put(&mut image, 16 * 4096 + 0x10040, b"_BHRfS_M");
+        let (names, changed) = wipe_image(&image);
+        assert_eq!(names, ["btrfs", "gpt"]);
+        assert_eq!(
+            changed,
+            [4096..4104, 0x20040..0x20048, LEN - 4096..LEN - 4088]
+        );
+    }
+
+    #[test]
+    fn blank_disk_is_left_alone() {
+        let image: Vec<u8> = (0..LEN).map(|i| (i % 251) as u8).collect();
+        assert_eq!(wipe_image(&image), (vec![], vec![]));
+    }
+}